@@ -12,6 +12,9 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .disk_write_to(writedir)
         .spawn()?;
 
-    child.wait()?;
+    let status = child.wait()?;
+    if !status.success() {
+        eprintln!("Shell terminated with {}", status);
+    }
     Ok(())
 }
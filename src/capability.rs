@@ -0,0 +1,148 @@
+use nix::errno::Errno;
+
+/// Version 3 of the `capget` and `capset` interface, with 64-bit sets
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Highest capability number, if `/proc/sys/kernel/cap_last_cap` can not be read
+const DEFAULT_LAST_CAP: u32 = 40;
+
+/// Capabilities kept with `Command::keep_capabilities`, see `capabilities(7)`.
+/// Each variant corresponds to the `CAP_` constant of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Capability {
+    Chown = 0,
+    DacOverride = 1,
+    DacReadSearch = 2,
+    Fowner = 3,
+    Fsetid = 4,
+    Kill = 5,
+    Setgid = 6,
+    Setuid = 7,
+    Setpcap = 8,
+    LinuxImmutable = 9,
+    NetBindService = 10,
+    NetBroadcast = 11,
+    NetAdmin = 12,
+    NetRaw = 13,
+    IpcLock = 14,
+    IpcOwner = 15,
+    SysModule = 16,
+    SysRawio = 17,
+    SysChroot = 18,
+    SysPtrace = 19,
+    SysPacct = 20,
+    SysAdmin = 21,
+    SysBoot = 22,
+    SysNice = 23,
+    SysResource = 24,
+    SysTime = 25,
+    SysTtyConfig = 26,
+    Mknod = 27,
+    Lease = 28,
+    AuditWrite = 29,
+    AuditControl = 30,
+    Setfcap = 31,
+    MacOverride = 32,
+    MacAdmin = 33,
+    Syslog = 34,
+    WakeAlarm = 35,
+    BlockSuspend = 36,
+    AuditRead = 37,
+    Perfmon = 38,
+    Bpf = 39,
+    CheckpointRestore = 40,
+}
+
+/// `struct __user_cap_header_struct`, not provided by libc
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+/// `struct __user_cap_data_struct`, two of which hold the 64-bit sets
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// Reads the capability sets of the current thread
+fn capget() -> nix::Result<[CapData; 2]> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    let res = unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) };
+    Errno::result(res).map(|_| data)
+}
+
+/// Writes the capability sets of the current thread
+fn capset(data: &[CapData; 2]) -> nix::Result<()> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let res = unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) };
+    Errno::result(res).map(drop)
+}
+
+fn mask(keep: &[Capability]) -> u64 {
+    keep.iter().fold(0_u64, |mask, &cap| mask | 1 << cap as u32)
+}
+
+/// Drops all capabilities except `keep` from the bounding and ambient sets
+/// of the current process. Requires `CAP_SETPCAP`, so this is done before
+/// the credentials change.
+pub(crate) fn drop_bounding(keep: &[Capability]) -> nix::Result<()> {
+    let mask = mask(keep);
+
+    // The bounding set limits what an exec can grant, even to root
+    let last_cap = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_LAST_CAP);
+    for cap in (0..=last_cap).filter(|cap| mask & 1 << cap == 0) {
+        let res = unsafe { libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) };
+        match Errno::result(res) {
+            // Not supported by the running kernel
+            Err(nix::Error::Sys(Errno::EINVAL)) => {}
+            other => other.map(drop)?,
+        }
+    }
+
+    // Ambient capabilities require Linux 4.3
+    let res = unsafe {
+        libc::prctl(
+            libc::PR_CAP_AMBIENT,
+            libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+            0,
+            0,
+            0,
+        )
+    };
+    match Errno::result(res) {
+        Err(nix::Error::Sys(Errno::EINVAL)) => Ok(()),
+        other => other.map(drop),
+    }
+}
+
+/// Drops all capabilities except `keep` from the effective, permitted and
+/// inheritable sets of the current process, after the credentials change
+pub(crate) fn restrict(keep: &[Capability]) -> nix::Result<()> {
+    let mask = mask(keep);
+
+    // Capabilities can not be added to the permitted set
+    let mut data = capget()?;
+    for (i, half) in data.iter_mut().enumerate() {
+        let keep = (mask >> (32 * i)) as u32;
+        half.permitted &= keep;
+        half.effective &= keep;
+        half.inheritable &= keep;
+    }
+    capset(&data)
+}
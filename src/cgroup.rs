@@ -0,0 +1,165 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use nix::sys::statfs::{statfs, CGROUP2_SUPER_MAGIC};
+use nix::unistd::Pid;
+
+use crate::{Error, Result};
+
+/// Directory under which the cgroups of processes are created by default
+pub(crate) const DEFAULT_CGROUP_BASE: &str = "/sys/fs/cgroup/isolated";
+
+/// Period of the CPU bandwidth limit in microseconds, the kernel default
+pub(crate) const CPU_PERIOD_US: u64 = 100_000;
+
+/// Control group v2 limits of a process, populated by `Command`
+#[derive(Debug, Clone)]
+pub(crate) struct CgroupConfig {
+    /// Parent of the per-process cgroups, created if it does not exist
+    pub(crate) base: PathBuf,
+    /// Value for `memory.max`
+    pub(crate) memory_max: Option<u64>,
+    /// Value for `pids.max`
+    pub(crate) pids_max: Option<u64>,
+    /// CPU bandwidth for `cpu.max`, as quota and period in microseconds
+    pub(crate) cpu_max: Option<(u64, u64)>,
+    /// Value for `cpu.weight`
+    pub(crate) cpu_weight: Option<u32>,
+}
+
+impl Default for CgroupConfig {
+    fn default() -> Self {
+        Self {
+            base: PathBuf::from(DEFAULT_CGROUP_BASE),
+            memory_max: None,
+            pids_max: None,
+            cpu_max: None,
+            cpu_weight: None,
+        }
+    }
+}
+
+impl CgroupConfig {
+    /// Whether any limits are set, so that a cgroup must be created
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.memory_max.is_some()
+            || self.pids_max.is_some()
+            || self.cpu_max.is_some()
+            || self.cpu_weight.is_some()
+    }
+
+    /// Controllers required by the configured limits
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() {
+            controllers.push("memory");
+        }
+        if self.pids_max.is_some() {
+            controllers.push("pids");
+        }
+        if self.cpu_max.is_some() || self.cpu_weight.is_some() {
+            controllers.push("cpu");
+        }
+        controllers
+    }
+}
+
+/// Whether the base directory, or its closest existing ancestor, is in a cgroup v2 hierarchy.
+/// Otherwise the directories would be created on another filesystem.
+pub(crate) fn is_available(base: &Path) -> bool {
+    let existing = base.ancestors().find(|path| path.exists());
+    existing
+        .and_then(|path| statfs(path).ok())
+        .is_some_and(|stat| stat.filesystem_type() == CGROUP2_SUPER_MAGIC)
+}
+
+/// Writes a value to an existing cgroup control file
+fn write_control(path: &Path, value: &str) -> Result<()> {
+    // Control files are never created, so a missing cgroup2 mount is an error
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()))
+        .map_err(|source| Error::Cgroup {
+            path: path.to_owned(),
+            source,
+        })
+}
+
+/// A cgroup created for a single process.
+/// On drop, any remaining processes in it are killed and the directory is removed.
+pub(crate) struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    /// Creates a cgroup for the process, applies the limits and moves the process into it
+    pub(crate) fn create(config: &CgroupConfig, pid: Pid) -> Result<Self> {
+        let dir_error = |path: &Path| {
+            let path = path.to_owned();
+            move |source| Error::Cgroup { path, source }
+        };
+
+        if !is_available(&config.base) {
+            return Err(Error::CgroupUnavailable(config.base.clone()));
+        }
+
+        // Controllers must be enabled in the parent to be usable in its children
+        fs::create_dir_all(&config.base).map_err(dir_error(&config.base))?;
+        let controllers: Vec<_> = config
+            .controllers()
+            .iter()
+            .map(|name| format!("+{}", name))
+            .collect();
+        write_control(
+            &config.base.join("cgroup.subtree_control"),
+            &controllers.join(" "),
+        )?;
+
+        let path = config.base.join(pid.to_string());
+        fs::create_dir(&path).map_err(dir_error(&path))?;
+        // Removed again if any of the following steps fails
+        let cgroup = Self { path };
+
+        if let Some(max) = config.memory_max {
+            write_control(&cgroup.path.join("memory.max"), &max.to_string())?;
+        }
+        if let Some(max) = config.pids_max {
+            write_control(&cgroup.path.join("pids.max"), &max.to_string())?;
+        }
+        if let Some((quota, period)) = config.cpu_max {
+            let value = format!("{} {}", quota, period);
+            write_control(&cgroup.path.join("cpu.max"), &value)?;
+        }
+        if let Some(weight) = config.cpu_weight {
+            write_control(&cgroup.path.join("cpu.weight"), &weight.to_string())?;
+        }
+        write_control(&cgroup.path.join("cgroup.procs"), &pid.to_string())?;
+        Ok(cgroup)
+    }
+
+    /// Number of processes killed by the OOM killer in this cgroup,
+    /// or zero if it could not be read
+    pub(crate) fn oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .ok()
+            .and_then(|events| {
+                events.lines().find_map(|line| {
+                    let count = line.strip_prefix("oom_kill ")?;
+                    count.parse().ok()
+                })
+            })
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // Errors are ignored, as nothing else can be done here.
+        // Killing requires Linux 5.14, but the processes of the container
+        // have usually exited with its init process already.
+        let _ = write_control(&self.path.join("cgroup.kill"), "1");
+        let _ = fs::remove_dir(&self.path);
+    }
+}
@@ -1,9 +1,74 @@
 use std::{
-    ffi::CString,
+    collections::BTreeMap,
+    ffi::{CString, NulError, OsStr, OsString},
+    net::Ipv4Addr,
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use crate::Process;
+use crate::cgroup::{CgroupConfig, CPU_PERIOD_US};
+use crate::{
+    Capability, Error, ExitStatus, NetworkPolicy, Output, Process, Resource, Result, SeccompPolicy,
+    Stdio,
+};
+
+/// Environment of the process unless configured otherwise
+const DEFAULT_PATH: &str = "/usr/bin:/bin";
+
+/// Stack size of the child process before exec
+const DEFAULT_CLONE_STACK_SIZE: usize = 1024 * 1024;
+
+/// Hostname of the container unless configured otherwise
+const DEFAULT_HOSTNAME: &str = "isolated";
+
+/// Paths hidden from the process unless configured otherwise, as in runc
+const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/firmware",
+];
+
+/// Maximum length of host and domain names, see `sethostname(2)`
+const MAX_UTS_NAME_LEN: usize = 64;
+
+/// Size of a tmpfs mount unless configured otherwise
+const DEFAULT_TMPFS_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Range of `cpu.weight` of a cgroup
+const CPU_WEIGHT_RANGE: std::ops::RangeInclusive<u32> = 1..=10000;
+
+/// Valid periods of `cpu.max` in microseconds
+const CPU_PERIOD_RANGE_US: std::ops::RangeInclusive<u64> = 1000..=1_000_000;
+
+/// Smallest quota of `cpu.max` in microseconds
+const MIN_CPU_QUOTA_US: u64 = 1000;
+
+/// Checks that the name is acceptable for `sethostname` or `setdomainname`
+fn validate_uts_name(name: &str) -> Result<()> {
+    if name.len() > MAX_UTS_NAME_LEN || name.contains('\0') {
+        return Err(Error::InvalidUtsName(name.to_owned()));
+    }
+    Ok(())
+}
+
+/// Panics if the path is not acceptable as a mount target inside the container
+fn validate_container_path(path: &Path) {
+    use std::path::Component;
+
+    assert!(path.is_absolute(), "Mount target must be absolute");
+    assert!(
+        !path.components().any(|c| c == Component::ParentDir),
+        "Mount target must not contain .."
+    );
+}
 
 #[derive(Debug, Clone)]
 pub(crate) enum DiskWritePolicy {
@@ -11,52 +76,268 @@ pub(crate) enum DiskWritePolicy {
     TempDir,
     /// Write modifications to the file system done by the application to this directory
     WriteDir(PathBuf),
+    /// Writes fail with `EROFS`
+    ReadOnly,
+}
+
+/// A host path bound into the container
+#[derive(Debug, Clone)]
+pub(crate) struct BindMount {
+    pub(crate) source: PathBuf,
+    /// Absolute path inside the container
+    pub(crate) target: PathBuf,
+    pub(crate) read_only: bool,
+}
+
+/// An in-memory filesystem mounted in the container
+#[derive(Debug, Clone)]
+pub(crate) struct TmpfsMount {
+    /// Absolute path inside the container
+    pub(crate) target: PathBuf,
+    pub(crate) size: u64,
+}
+
+/// A mount inside the container, applied in registration order
+#[derive(Debug, Clone)]
+pub(crate) enum Mount {
+    Bind(BindMount),
+    Tmpfs(TmpfsMount),
+}
+
+/// UID and GID mappings of a user namespace.
+/// Each entry is `(inside, outside, count)`, as in `/proc/[pid]/uid_map`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct IdMaps {
+    pub(crate) uid_map: Vec<(u32, u32, u32)>,
+    pub(crate) gid_map: Vec<(u32, u32, u32)>,
 }
 
-type Hook = dyn FnOnce() -> nix::Result<()>;
+/// Hook called in the child process before exec.
+/// The argument is reserved for context information about the process.
+///
+/// The hook runs in a copy of the parent process that has a single thread.
+/// If other threads of the parent held locks at the time of the clone,
+/// for instance the allocator lock or the stdout lock, they are never released,
+/// so the hook should avoid allocating and printing if the parent is
+/// multi-threaded. A panic terminates the child without starting the program.
+pub type Hook = dyn FnOnce(()) -> nix::Result<()>;
 
 /// Offers an API similar to `std::process::Command`.
 #[must_use]
 pub struct Command {
     /// Command path inside the isolated filesystem
     pub(crate) path: CString,
-    /// Command arguments
-    pub(crate) args: Vec<CString>,
+    /// Command arguments, after the path which is passed as `argv[0]`
+    pub(crate) args: Vec<OsString>,
+    /// Working directory inside the container
+    pub(crate) current_dir: Option<PathBuf>,
+    /// Environment variables of the process.
+    /// The host environment is never inherited.
+    pub(crate) env: BTreeMap<OsString, OsString>,
     /// OverlayFS layers from outermost to innermost, usually `[rootfs, appdir]`
     /// where rootfs contains a linux root file system like Alpine minirootfs,
     /// and `appdir` is the directory where the application binary is located.
     /// All of the layers are overlayed on the root of the container file system.
     pub(crate) layers: Vec<PathBuf>,
+    /// Upper directories stacked on the layers, from innermost to outermost
+    pub(crate) writable_layers: Vec<PathBuf>,
     /// Disk write access
     pub(crate) disk_write: DiskWritePolicy,
+    /// Whether to use overlayfs even for a single read-only layer
+    pub(crate) force_overlayfs: bool,
+    /// Binds and tmpfs mounts inside the container, in order
+    pub(crate) mounts: Vec<Mount>,
+    /// Paths hidden inside the container, in order
+    pub(crate) masked_paths: Vec<PathBuf>,
+    /// Whether `/sys` is mounted writable
+    pub(crate) writable_sys: bool,
+    /// Whether `/proc` hides the processes of other users
+    pub(crate) hide_pids: bool,
+    /// Whether to create a minimal `/dev`
+    pub(crate) mount_dev: bool,
+    /// Whether to mount a new `/dev/pts` instance
+    pub(crate) dev_pts: bool,
     /// Called just before pivot_root, after fork
     pub(crate) pre_pivot: Vec<Box<Hook>>,
     /// Called just before exec'ing new process, after fork and pivot_root
     pub(crate) pre_exec: Vec<Box<Hook>>,
+    /// Whether to use a new IPC namespace
+    pub(crate) ipc_namespace: bool,
+    /// Network namespace and interfaces
+    pub(crate) network: NetworkPolicy,
+    /// Hostname in the UTS namespace of the container
+    pub(crate) hostname: String,
+    /// NIS domain name in the UTS namespace of the container
+    pub(crate) domainname: Option<String>,
+    /// User id of the process inside the container
+    pub(crate) uid: Option<u32>,
+    /// Group id of the process inside the container
+    pub(crate) gid: Option<u32>,
+    /// Supplementary groups of the process inside the container
+    pub(crate) groups: Option<Vec<u32>>,
+    /// Resource limits as `(resource, soft, hard)`
+    pub(crate) rlimits: Vec<(Resource, u64, u64)>,
+    /// Limits enforced with a cgroup
+    pub(crate) cgroup: CgroupConfig,
+    /// Maximum number of processes, using a cgroup if available
+    pub(crate) max_pids: Option<u64>,
+    /// Wall-clock time after which the process is killed
+    pub(crate) time_limit: Option<Duration>,
+    /// Time between `SIGTERM` and `SIGKILL` when shutting down
+    pub(crate) grace_period: Option<Duration>,
+    /// System call filter installed just before exec
+    pub(crate) seccomp: Option<SeccompPolicy>,
+    /// Whether to set `no_new_privs` before exec
+    pub(crate) no_new_privs: bool,
+    /// Whether the root filesystem is mounted with `MS_NOSUID`
+    pub(crate) nosuid: bool,
+    /// Capabilities kept before exec, or all of them if `None`
+    pub(crate) capabilities: Option<Vec<Capability>>,
+    /// User namespace mappings, if a user namespace should be created
+    pub(crate) user_namespace: Option<IdMaps>,
+    /// Panic if the process is dropped without waiting for it
+    pub(crate) must_wait: bool,
+    /// Whether a minimal init process runs as PID 1, see `use_init`
+    pub(crate) use_init: bool,
+    /// Stack size for the child process, used before exec
+    pub(crate) clone_stack_size: usize,
+    /// Standard input of the process
+    pub(crate) stdin: Stdio,
+    /// Standard output of the process
+    pub(crate) stdout: Stdio,
+    /// Standard error of the process
+    pub(crate) stderr: Stdio,
 }
 impl Command {
     /// Command path inside the isolated filesystem.
-    /// Panics if path contains null bytes.
+    /// Panics if path contains null bytes, see `try_new` for a fallible version.
+    #[track_caller]
     pub fn new<P: AsRef<Path>>(root_fs: P, path: &str) -> Self {
-        let path = CString::new(path.as_bytes().to_vec()).expect("Nul byte in target path");
-        Self {
-            path: path.clone(),
-            args: vec![path],
+        Self::try_new(root_fs, path).expect("Nul byte in target path")
+    }
+
+    /// Like `new`, but returns an error if path contains null bytes.
+    pub fn try_new<P: AsRef<Path>>(root_fs: P, path: &str) -> std::result::Result<Self, NulError> {
+        let path = CString::new(path.as_bytes().to_vec())?;
+        Ok(Self {
+            path,
+            args: Vec::new(),
+            current_dir: None,
+            env: std::iter::once(("PATH".into(), DEFAULT_PATH.into())).collect(),
             layers: vec![root_fs.as_ref().to_owned()],
+            writable_layers: Vec::new(),
             disk_write: DiskWritePolicy::TempDir,
+            force_overlayfs: false,
+            mounts: Vec::new(),
+            masked_paths: DEFAULT_MASKED_PATHS.iter().map(PathBuf::from).collect(),
+            writable_sys: false,
+            hide_pids: false,
+            mount_dev: true,
+            dev_pts: false,
             pre_pivot: Vec::new(),
             pre_exec: Vec::new(),
-        }
+            ipc_namespace: true,
+            network: NetworkPolicy::Isolated,
+            hostname: DEFAULT_HOSTNAME.to_owned(),
+            domainname: None,
+            uid: None,
+            gid: None,
+            groups: None,
+            rlimits: Vec::new(),
+            cgroup: CgroupConfig::default(),
+            max_pids: None,
+            time_limit: None,
+            grace_period: None,
+            seccomp: None,
+            no_new_privs: true,
+            nosuid: true,
+            capabilities: None,
+            user_namespace: None,
+            must_wait: false,
+            use_init: false,
+            clone_stack_size: DEFAULT_CLONE_STACK_SIZE,
+            stdin: Stdio::Inherit,
+            stdout: Stdio::Inherit,
+            stderr: Stdio::Inherit,
+        })
     }
 
+    /// Replaces the arguments of the process, keeping the path as `argv[0]`,
+    /// so this also discards arguments added with `arg` before.
     /// Panics if any argument contains null bytes.
     pub fn args(mut self, args: &[&str]) -> Self {
-        self.args =
-            std::iter::once(self.path.clone())
-                .chain(args.iter().map(|arg| {
-                    CString::new(arg.as_bytes().to_vec()).expect("Nul byte in an argument")
-                }))
-                .collect();
+        assert!(
+            args.iter().all(|arg| !arg.contains('\0')),
+            "Nul byte in an argument"
+        );
+        self.args = args.iter().map(OsString::from).collect();
+        self
+    }
+
+    /// Appends a single argument, after those set with `args` or `arg`.
+    /// Panics if the argument contains null bytes.
+    pub fn arg(mut self, arg: &str) -> Self {
+        assert!(!arg.contains('\0'), "Nul byte in an argument");
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Like `args`, but the arguments do not need to be valid UTF-8.
+    /// An argument containing a null byte makes spawning fail with
+    /// `Error::InvalidArg` instead of panicking.
+    pub fn args_os(mut self, args: &[&OsStr]) -> Self {
+        self.args = args.iter().map(OsString::from).collect();
+        self
+    }
+
+    /// Like `arg`, but the argument does not need to be valid UTF-8.
+    /// An argument containing a null byte makes spawning fail with
+    /// `Error::InvalidArg` instead of panicking.
+    pub fn arg_os(mut self, arg: &OsStr) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Sets the working directory of the process inside the container.
+    /// Relative paths are resolved against the container root.
+    /// If the directory doesn't exist, `spawn` returns `Error::CurrentDir`.
+    pub fn current_dir<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.current_dir = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Sets an environment variable for the process.
+    /// By default, only `PATH=/usr/bin:/bin` is set, and the host environment
+    /// is not inherited. Keys or values containing nul bytes, and keys
+    /// containing `=`, cause `spawn` to return `Error::InvalidEnv`.
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(mut self, key: K, val: V) -> Self {
+        self.env
+            .insert(key.as_ref().to_owned(), val.as_ref().to_owned());
+        self
+    }
+
+    /// Sets multiple environment variables for the process, see `env`
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        for (key, val) in vars {
+            self = self.env(key, val);
+        }
+        self
+    }
+
+    /// Removes an environment variable of the process
+    pub fn env_remove<K: AsRef<OsStr>>(mut self, key: K) -> Self {
+        self.env.remove(key.as_ref());
+        self
+    }
+
+    /// Clears all environment variables of the process, including the default `PATH`
+    pub fn env_clear(mut self) -> Self {
+        self.env.clear();
         self
     }
 
@@ -66,22 +347,147 @@ impl Command {
         self
     }
 
-    /// Allows disk writes to a temporary directory
+    /// Stacks a writable directory on top of the read-only layers and the
+    /// previously added writable layers, using an overlay mount of its own.
+    /// Overlayfs supports a single upper directory, so the overlays are chained,
+    /// and the writes of the container still go to the writedir on top.
+    ///
+    /// The kernel limits the nesting of overlays to two levels, so currently
+    /// only a single writable layer can be mounted, and none if the layers are
+    /// themselves on an overlay. Deeper chains fail with `Error::Mount`.
+    pub fn writable_layer<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.writable_layers.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Allows disk writes to a temporary directory, which are discarded when
+    /// the process is dropped. This is the default.
     pub fn disk_write_tempdir(mut self) -> Self {
         self.disk_write = DiskWritePolicy::TempDir;
         self
     }
 
-    /// Allows disk writes to a temporary directory
+    /// Allows disk writes to the given directory, which is the upper
+    /// layer of the overlay filesystem
     pub fn disk_write_to<P: AsRef<Path>>(mut self, path: P) -> Self {
         self.disk_write = DiskWritePolicy::WriteDir(path.as_ref().to_owned());
         self
     }
 
+    /// Makes the root filesystem read-only, so that writes fail with `EROFS`.
+    /// Binds and tmpfs mounts can still be writable.
+    ///
+    /// With a single layer, it is bound directly instead of using overlayfs,
+    /// so it must already contain the mount points, like `/proc`, `/sys` and
+    /// `/dev`, and the targets of binds and tmpfs mounts.
+    pub fn read_only(mut self) -> Self {
+        self.disk_write = DiskWritePolicy::ReadOnly;
+        self
+    }
+
+    /// Uses overlayfs even when a single layer could be bound directly,
+    /// see `read_only`. Mainly useful for testing.
+    pub fn force_overlayfs(mut self, enabled: bool) -> Self {
+        self.force_overlayfs = enabled;
+        self
+    }
+
+    /// Binds a host file or directory to `container_path`, which must be absolute.
+    /// The target is created in the container if it does not exist.
+    /// Writes to a writable bind go directly to the host, not to the writedir.
+    /// Binds and tmpfs mounts are applied in the order they were added.
+    /// Panics if `container_path` is relative or contains `..`.
+    pub fn bind<P: AsRef<Path>, Q: AsRef<Path>>(
+        mut self,
+        host_path: P,
+        container_path: Q,
+        read_only: bool,
+    ) -> Self {
+        let target = container_path.as_ref();
+        validate_container_path(target);
+        self.mounts.push(Mount::Bind(BindMount {
+            source: host_path.as_ref().to_owned(),
+            target: target.to_owned(),
+            read_only,
+        }));
+        self
+    }
+
+    /// Mounts an in-memory filesystem at `container_path`, which must be absolute,
+    /// creating the directory if needed. The contents are discarded when the
+    /// container exits, and do not go to the writedir. The size defaults
+    /// to 64 MiB. Binds and tmpfs mounts are applied in the order they were
+    /// added, so a later mount can be placed inside an earlier one, or hide
+    /// it. `/dev/shm` is replaced when given.
+    /// Panics if `container_path` is relative or contains `..`.
+    pub fn tmpfs<P: AsRef<Path>>(mut self, container_path: P, size_bytes: Option<u64>) -> Self {
+        let target = container_path.as_ref();
+        validate_container_path(target);
+        self.mounts.push(Mount::Tmpfs(TmpfsMount {
+            target: target.to_owned(),
+            size: size_bytes.unwrap_or(DEFAULT_TMPFS_SIZE),
+        }));
+        self
+    }
+
+    /// Hides a path inside the container, after the pseudo-filesystems have
+    /// been mounted. A file is replaced with `/dev/null` of the container, and
+    /// a directory with an empty read-only tmpfs. Missing paths are ignored.
+    /// By default, sensitive paths like `/proc/kcore` are masked, and `/proc/sys`
+    /// is read-only. Panics if `path` is relative or contains `..`.
+    pub fn mask_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        validate_container_path(path.as_ref());
+        self.masked_paths.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Replaces the list of masked paths, see `mask_path`.
+    /// An empty list disables the default masking, but `/proc/sys` and
+    /// the other read-only paths stay read-only.
+    /// Panics if any path is relative or contains `..`.
+    pub fn masked_paths(mut self, paths: &[&str]) -> Self {
+        self.masked_paths.clear();
+        for path in paths {
+            self = self.mask_path(path);
+        }
+        self
+    }
+
+    /// Controls whether `/sys` is writable in the container. Disabled by default,
+    /// as root in the container could otherwise change settings of the host.
+    pub fn writable_sys(mut self, enabled: bool) -> Self {
+        self.writable_sys = enabled;
+        self
+    }
+
+    /// Mounts `/proc` with `hidepid=2`, so that processes of other users in
+    /// the container are hidden from each other. Disabled by default.
+    pub fn hide_pids(mut self, enabled: bool) -> Self {
+        self.hide_pids = enabled;
+        self
+    }
+
+    /// Controls whether `/dev` is replaced with a tmpfs containing the device
+    /// nodes `null`, `zero`, `full`, `random`, `urandom` and `tty` of the host,
+    /// the usual `/dev/fd` symlinks, and an empty `/dev/shm`. Enabled by default. When disabled,
+    /// `/dev` of the root filesystem is used as is.
+    pub fn mount_dev(mut self, enabled: bool) -> Self {
+        self.mount_dev = enabled;
+        self
+    }
+
+    /// Controls whether a new instance of `/dev/pts` is mounted, with the
+    /// `/dev/ptmx` symlink, so that the process can create pseudoterminals.
+    /// Disabled by default. Has no effect unless `mount_dev` is enabled.
+    pub fn dev_pts(mut self, enabled: bool) -> Self {
+        self.dev_pts = enabled;
+        self
+    }
+
     /// Hook is called just before pivot_root, after fork.
     /// If multiple hooks are registered, they will be called in order.
     /// If any hook returns an error, no more hooks will be called, and
-    /// the process will not be started.
+    /// the process will not be started, and `spawn` returns `Error::Hook`.
     pub fn hook_pre_pivot(mut self, hook: Box<Hook>) -> Self {
         self.pre_pivot.push(hook);
         self
@@ -90,13 +496,432 @@ impl Command {
     /// Hook is called just before exec, after fork and pivot_root.
     /// If multiple hooks are registered, they will be called in order.
     /// If any hook returns an error, no more hooks will be called, and
-    /// the process will not be started.
+    /// the process will not be started, and `spawn` returns `Error::Hook`.
     pub fn hook_pre_exec(mut self, hook: Box<Hook>) -> Self {
         self.pre_exec.push(hook);
         self
     }
 
-    pub fn spawn(self) -> nix::Result<Process> {
+    /// Controls whether the process gets a separate System V IPC namespace,
+    /// and a fresh `/dev/shm` for POSIX shared memory. Enabled by default.
+    pub fn ipc_namespace(mut self, enabled: bool) -> Self {
+        self.ipc_namespace = enabled;
+        self
+    }
+
+    /// Configures the network access of the process.
+    /// Defaults to `NetworkPolicy::Isolated`, where only sockets on
+    /// the loopback interface, like `127.0.0.1`, can be used.
+    pub fn network(mut self, policy: NetworkPolicy) -> Self {
+        self.network = policy;
+        self
+    }
+
+    /// Shares the network namespace of the host with the process, instead of
+    /// creating an isolated one. Shorthand for `network(NetworkPolicy::Host)`,
+    /// useful for package managers and DNS.
+    pub fn share_host_net(self) -> Self {
+        self.network(NetworkPolicy::Host)
+    }
+
+    /// Connects the network namespace of the process to the host with a veth
+    /// pair, see `NetworkPolicy::Veth`. The interfaces are removed when the
+    /// process has been waited for or dropped. Panics if `prefix_len` is above 32.
+    pub fn network_veth(self, host_ip: Ipv4Addr, container_ip: Ipv4Addr, prefix_len: u8) -> Self {
+        assert!(prefix_len <= 32, "Invalid prefix length {}", prefix_len);
+        self.network(NetworkPolicy::Veth {
+            host_addr: host_ip,
+            container_addr: container_ip,
+            prefix_len,
+            enable_nat: false,
+        })
+    }
+
+    /// Controls whether the loopback interface of the network namespace is
+    /// brought up, choosing between `NetworkPolicy::Isolated` and
+    /// `NetworkPolicy::None`. Has no effect with `share_host_net` or `network_veth`.
+    pub fn enable_loopback(self, enabled: bool) -> Self {
+        match self.network {
+            NetworkPolicy::Host | NetworkPolicy::Veth { .. } => self,
+            _ if enabled => self.network(NetworkPolicy::Isolated),
+            _ => self.network(NetworkPolicy::None),
+        }
+    }
+
+    /// Sets the hostname of the container. The container always has its own
+    /// UTS namespace, so the host is not affected. Defaults to `isolated`.
+    /// Spawning fails with `Error::InvalidUtsName` if the name is longer
+    /// than 64 bytes or contains nul bytes.
+    pub fn hostname(mut self, name: &str) -> Self {
+        self.hostname = name.to_owned();
+        self
+    }
+
+    /// Sets the NIS domain name of the container. Otherwise the domain name
+    /// of the host is inherited.
+    /// Spawning fails with `Error::InvalidUtsName` if the name is longer
+    /// than 64 bytes or contains nul bytes.
+    pub fn domainname(mut self, name: &str) -> Self {
+        self.domainname = Some(name.to_owned());
+        self
+    }
+
+    /// Runs the process as the given user, instead of root.
+    /// With a user namespace, the id must be mapped inside the container.
+    /// Without root privileges and a configured user namespace, one is created
+    /// where this user and the `gid` group are mapped to the current ones.
+    /// If changing the user fails, `spawn` returns an error.
+    pub fn uid(mut self, uid: u32) -> Self {
+        self.uid = Some(uid);
+        self
+    }
+
+    /// Runs the process with the given primary group, instead of root
+    pub fn gid(mut self, gid: u32) -> Self {
+        self.gid = Some(gid);
+        self
+    }
+
+    /// Sets the supplementary groups of the process.
+    /// By default, they are cleared if `uid` or `gid` is set.
+    /// Not permitted in a user namespace created without root privileges.
+    pub fn groups(mut self, groups: &[u32]) -> Self {
+        self.groups = Some(groups.to_vec());
+        self
+    }
+
+    /// Limits a resource of the process with `setrlimit`.
+    /// Use `RLIM_INFINITY` for no limit. Setting the same resource again
+    /// replaces the previous limit. Limits are applied in the container
+    /// before changing the user, so hard limits can be raised too.
+    /// If `soft` is above `hard`, `spawn` returns `Error::InvalidRlimit`.
+    pub fn rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.rlimits.retain(|(r, _, _)| *r != resource);
+        self.rlimits.push((resource, soft, hard));
+        self
+    }
+
+    /// Limits the number of open file descriptors, see `rlimit`
+    pub fn rlimit_nofile(self, max: u64) -> Self {
+        self.rlimit(Resource::OpenFiles, max, max)
+    }
+
+    /// Limits the CPU time of the process in seconds, see `rlimit`.
+    /// The process gets `SIGXCPU` at the limit, and `SIGKILL` one second later.
+    pub fn rlimit_cpu(self, seconds: u64) -> Self {
+        self.rlimit(Resource::CpuTime, seconds, seconds + 1)
+    }
+
+    /// Limits the virtual memory of the process in bytes, see `rlimit`
+    pub fn rlimit_as(self, bytes: u64) -> Self {
+        self.rlimit(Resource::AddressSpace, bytes, bytes)
+    }
+
+    /// Limits the size of core dumps in bytes, see `rlimit`.
+    /// Zero disables core dumps.
+    pub fn rlimit_core(self, bytes: u64) -> Self {
+        self.rlimit(Resource::CoreSize, bytes, bytes)
+    }
+
+    /// Limits the memory usage of the process and its descendants to
+    /// `limit` bytes, using `memory.max` of a new cgroup v2.
+    /// When the limit is reached, the processes are killed by the OOM killer,
+    /// which can be detected with `Process::oom_killed`.
+    /// If cgroup v2 is not available, `spawn` returns `Error::CgroupUnavailable`.
+    pub fn memory_limit_bytes(mut self, limit: u64) -> Self {
+        self.cgroup.memory_max = Some(limit);
+        self
+    }
+
+    /// Limits the number of processes and threads in the container to `limit`,
+    /// using `pids.max` of the same cgroup as `memory_limit_bytes`.
+    /// Prevents fork bombs from exhausting the pids of the host.
+    pub fn pids_limit(mut self, limit: u64) -> Self {
+        self.cgroup.pids_max = Some(limit);
+        self
+    }
+
+    /// Limits the number of processes and threads to `max`, like `pids_limit`,
+    /// but falls back to `RLIMIT_NPROC` if cgroup v2 is not mounted.
+    /// The fallback counts all processes of the user on the host, and does
+    /// not apply to root, so it should be combined with `uid`.
+    /// The mechanism used is reported by `Process::pid_limit_method`.
+    /// Forks over the limit fail with `EAGAIN`.
+    pub fn max_pids(mut self, max: u32) -> Self {
+        self.max_pids = Some(max.into());
+        self
+    }
+
+    /// Limits the CPU time of the container to `max_percent` percent of a
+    /// single CPU, using `cpu.max` of the same cgroup as `memory_limit_bytes`.
+    /// Values over 100 allow using multiple CPUs. Panics if `max_percent` is zero.
+    pub fn cpu_limit(mut self, max_percent: u32) -> Self {
+        assert!(max_percent > 0, "CPU limit must be positive");
+        let quota = CPU_PERIOD_US * u64::from(max_percent) / 100;
+        self.cgroup.cpu_max = Some((quota, CPU_PERIOD_US));
+        self
+    }
+
+    /// Limits the CPU time of the container to `fraction` of a single CPU,
+    /// like `cpu_limit`. For instance `0.5` is half a core.
+    /// Panics if `fraction` is not positive, or is too small for the 100 ms period.
+    pub fn cpu_limit_fraction(self, fraction: f64) -> Self {
+        assert!(
+            fraction.is_finite() && fraction > 0.0,
+            "CPU limit must be positive"
+        );
+        let quota = Duration::from_secs_f64(fraction * CPU_PERIOD_US as f64 / 1e6);
+        self.cpu_quota(Duration::from_micros(CPU_PERIOD_US), quota)
+    }
+
+    /// Allows the container to use `quota` of CPU time in each `period`,
+    /// using `cpu.max` of the same cgroup as `memory_limit_bytes`.
+    /// A quota longer than the period allows using multiple CPUs.
+    /// Panics if the period is not between 1 ms and 1 s, or the quota is below 1 ms.
+    pub fn cpu_quota(mut self, period: Duration, quota: Duration) -> Self {
+        let period = period.as_micros() as u64;
+        let quota = quota.as_micros() as u64;
+        assert!(
+            CPU_PERIOD_RANGE_US.contains(&period),
+            "CPU period must be between 1 ms and 1 s"
+        );
+        assert!(quota >= MIN_CPU_QUOTA_US, "CPU quota must be at least 1 ms");
+        self.cgroup.cpu_max = Some((quota, period));
+        self
+    }
+
+    /// Sets the relative CPU share of the container compared to other
+    /// cgroups, using `cpu.weight`. The default weight of cgroups is 100.
+    /// Panics if the weight is not between 1 and 10000.
+    pub fn cpu_weight(mut self, weight: u32) -> Self {
+        assert!(
+            CPU_WEIGHT_RANGE.contains(&weight),
+            "CPU weight must be between 1 and 10000"
+        );
+        self.cgroup.cpu_weight = Some(weight);
+        self
+    }
+
+    /// Sets the directory under which a cgroup is created for each process
+    /// that has cgroup limits, like `memory_limit_bytes` or `cpu_limit`.
+    /// It must be inside a cgroup v2 hierarchy.
+    /// Defaults to `/sys/fs/cgroup/isolated`, which is created if needed.
+    pub fn cgroup_base<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.cgroup.base = path.as_ref().to_owned();
+        self
+    }
+
+    /// Kills the process with `SIGKILL` if it is still running after `limit`,
+    /// measured from `spawn`. Use `Process::timed_out` to find out whether
+    /// the process was killed because of this. Requires Linux 5.3 or newer.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Sets the time the process has to exit after `SIGTERM`, before it is
+    /// killed with `SIGKILL`, when stopped with `Process::shutdown` or dropped.
+    /// Without this, a dropped process is killed immediately.
+    /// PID 1 ignores `SIGTERM` without a handler, so this is mostly
+    /// useful together with `use_init`, which forwards it to the program.
+    pub fn grace_period(mut self, period: Duration) -> Self {
+        self.grace_period = Some(period);
+        self
+    }
+
+    /// Drops all capabilities of the process, see `keep_capabilities`
+    pub fn drop_all_capabilities(self) -> Self {
+        self.keep_capabilities(&[])
+    }
+
+    /// Drops all capabilities except the given ones from every capability set,
+    /// including the bounding set, after the container has been set up.
+    /// With a `uid` other than root, the process has no capabilities after
+    /// exec anyway, but the bounding set still limits what setuid binaries
+    /// and file capabilities can grant.
+    pub fn keep_capabilities(mut self, capabilities: &[Capability]) -> Self {
+        self.capabilities = Some(capabilities.to_vec());
+        self
+    }
+
+    /// Controls whether `no_new_privs` is set just before exec, so that setuid
+    /// and setgid binaries or file capabilities do not grant privileges to the
+    /// process. Enabled by default. A seccomp filter always sets it.
+    pub fn no_new_privs(mut self, enabled: bool) -> Self {
+        self.no_new_privs = enabled;
+        self
+    }
+
+    /// Controls whether the root filesystem is mounted with `MS_NOSUID`, so that
+    /// setuid and setgid bits and file capabilities in the layers are ignored
+    /// even without `no_new_privs`. Enabled by default. Both must be disabled
+    /// for setuid binaries like `sudo` to work in the container.
+    pub fn nosuid(mut self, enabled: bool) -> Self {
+        self.nosuid = enabled;
+        self
+    }
+
+    /// Restricts the system calls of the process with a seccomp filter.
+    /// The filter is installed after the hooks, just before exec,
+    /// so the policy must allow `execve`. Also sets `no_new_privs`,
+    /// so setuid binaries do not gain privileges in the container.
+    pub fn seccomp(mut self, policy: SeccompPolicy) -> Self {
+        self.seccomp = Some(policy);
+        self
+    }
+
+    /// Runs the process in a new user namespace with given UID and GID mappings.
+    /// Each mapping entry is `(inside, outside, count)`, as in `/proc/[pid]/uid_map`.
+    /// Mapping the current user to UID 0 inside the container allows
+    /// creating containers without root privileges on the host.
+    /// In this mode, the overlay filesystem is mounted inside the container.
+    pub fn user_namespace(
+        mut self,
+        uid_map: &[(u32, u32, u32)],
+        gid_map: &[(u32, u32, u32)],
+    ) -> Self {
+        self.user_namespace = Some(IdMaps {
+            uid_map: uid_map.to_vec(),
+            gid_map: gid_map.to_vec(),
+        });
+        self
+    }
+
+    /// Adds an entry to the UID mapping of the user namespace, creating the
+    /// namespace if needed, see `user_namespace`. The GID mapping must
+    /// be configured as well, for instance with `gid_map`.
+    pub fn uid_map(mut self, inside: u32, outside: u32, count: u32) -> Self {
+        let maps = self.user_namespace.get_or_insert_with(IdMaps::default);
+        maps.uid_map.push((inside, outside, count));
+        self
+    }
+
+    /// Adds an entry to the GID mapping of the user namespace, see `uid_map`
+    pub fn gid_map(mut self, inside: u32, outside: u32, count: u32) -> Self {
+        let maps = self.user_namespace.get_or_insert_with(IdMaps::default);
+        maps.gid_map.push((inside, outside, count));
+        self
+    }
+
+    /// Dropping the `Process` without waiting for it panics, instead of
+    /// killing the process. Useful for catching mistakes in process management.
+    pub fn must_wait(mut self) -> Self {
+        self.must_wait = true;
+        self
+    }
+
+    /// Runs a minimal init process as PID 1 of the container, like `docker --init`.
+    /// It forks the program, reaps orphaned processes that would otherwise
+    /// remain zombies, and forwards termination signals to the program.
+    /// The exit code of the init process is the exit code of the program,
+    /// or 128 plus the signal number if the program was killed by a signal.
+    /// `Process::id` is then the id of the init process. Only the program keeps
+    /// the stdio of the command, as init closes its files after the fork.
+    pub fn use_init(mut self, enabled: bool) -> Self {
+        self.use_init = enabled;
+        self
+    }
+
+    /// Runs the process in a new user namespace, where the current user and
+    /// group are mapped to root. This allows creating containers without
+    /// root privileges on the host, see `user_namespace`.
+    pub fn rootless(self) -> Self {
+        let uid = nix::unistd::getuid().as_raw();
+        let gid = nix::unistd::getgid().as_raw();
+        self.user_namespace(&[(0, uid, 1)], &[(0, gid, 1)])
+    }
+
+    /// Sets the size of the stack used by the child process before exec,
+    /// i.e. during container setup and hooks. Defaults to 1 MiB.
+    /// The stack is mapped separately with a guard page below it, so an
+    /// overflow kills the child with `SIGSEGV` before the program is started.
+    pub fn clone_stack_size(mut self, bytes: usize) -> Self {
+        self.clone_stack_size = bytes;
+        self
+    }
+
+    /// Configures the standard input of the process
+    pub fn stdin(mut self, cfg: Stdio) -> Self {
+        self.stdin = cfg;
+        self
+    }
+
+    /// Configures the standard output of the process
+    pub fn stdout(mut self, cfg: Stdio) -> Self {
+        self.stdout = cfg;
+        self
+    }
+
+    /// Configures the standard error of the process
+    pub fn stderr(mut self, cfg: Stdio) -> Self {
+        self.stderr = cfg;
+        self
+    }
+
+    pub fn spawn(self) -> Result<Process> {
         Process::spawn(self)
     }
+
+    /// Runs the process to completion, collecting its stdout and stderr.
+    /// Stdin is connected to `/dev/null`, and stdout and stderr to pipes,
+    /// overriding the configuration. Similar to `std::process::Command::output`.
+    pub fn output(self) -> Result<Output> {
+        self.stdin(Stdio::Null)
+            .stdout(Stdio::Piped)
+            .stderr(Stdio::Piped)
+            .spawn()?
+            .output()
+    }
+
+    /// Runs the process to completion, and returns its exit status.
+    /// Similar to `std::process::Command::status`.
+    pub fn status(self) -> Result<ExitStatus> {
+        self.spawn()?.wait()
+    }
+
+    /// Arguments for `execve`, starting with the path as `argv[0]`
+    pub(crate) fn arg_strings(&self) -> Result<Vec<CString>> {
+        std::iter::once(Ok(self.path.clone()))
+            .chain(self.args.iter().map(|arg| {
+                CString::new(arg.as_bytes()).map_err(|_| Error::InvalidArg(arg.clone()))
+            }))
+            .collect()
+    }
+
+    /// Environment variables in the `KEY=VALUE` format of `execve`
+    pub(crate) fn env_strings(&self) -> Result<Vec<CString>> {
+        self.env
+            .iter()
+            .map(|(key, val)| {
+                let invalid = || Error::InvalidEnv(key.clone());
+                if key.is_empty() || key.as_bytes().contains(&b'=') {
+                    return Err(invalid());
+                }
+                let mut pair = key.as_bytes().to_vec();
+                pair.push(b'=');
+                pair.extend_from_slice(val.as_bytes());
+                CString::new(pair).map_err(|_| invalid())
+            })
+            .collect()
+    }
+
+    /// Checks the resource limits before spawning, as `setrlimit` would
+    /// only report `EINVAL` from the container
+    pub(crate) fn validate_rlimits(&self) -> Result<()> {
+        match self.rlimits.iter().find(|(_, soft, hard)| soft > hard) {
+            Some(&(resource, soft, hard)) => Err(Error::InvalidRlimit {
+                resource,
+                soft,
+                hard,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the host and domain names before spawning, as they are only
+    /// set in the container
+    pub(crate) fn validate_uts_names(&self) -> Result<()> {
+        validate_uts_name(&self.hostname)?;
+        self.domainname.as_deref().map_or(Ok(()), validate_uts_name)
+    }
 }
@@ -0,0 +1,424 @@
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use nix::errno::Errno;
+
+use crate::Resource;
+
+/// Errors from spawning and managing isolated processes
+#[derive(Debug)]
+pub enum Error {
+    /// Mounting a filesystem, or one of its layers, failed
+    Mount { path: PathBuf, source: Errno },
+    /// Creating the child process failed
+    Clone(Errno),
+    /// Creating or configuring a user namespace failed,
+    /// for instance because user namespaces are disabled on the system
+    UserNamespace(Errno),
+    /// Executing the target program failed in the child process
+    Exec { path: PathBuf, source: Errno },
+    /// Changing to the working directory failed in the child process
+    CurrentDir { path: PathBuf, source: Errno },
+    /// Creating the temporary directory of the container failed
+    TempDir(io::Error),
+    /// Creating or configuring the cgroup of the process failed,
+    /// for instance because cgroup v2 is not mounted at the path
+    Cgroup { path: PathBuf, source: io::Error },
+    /// Cgroup limits were configured, but the cgroup base directory
+    /// is not inside a cgroup v2 hierarchy
+    CgroupUnavailable(PathBuf),
+    /// Configuring the network interfaces of the host for the container failed
+    Network(Errno),
+    /// A path or an argument could not be passed to the kernel
+    InvalidPath,
+    /// An environment variable with this key contained a nul byte,
+    /// or the key itself was empty or contained `=`
+    InvalidEnv(OsString),
+    /// An argument added with `Command::arg_os` or `Command::args_os`
+    /// contained a nul byte
+    InvalidArg(OsString),
+    /// A host or domain name was longer than 64 bytes or contained a nul byte
+    InvalidUtsName(String),
+    /// The soft limit of a resource was above its hard limit
+    InvalidRlimit {
+        resource: Resource,
+        soft: u64,
+        hard: u64,
+    },
+    /// Setting up the container failed in the child process
+    Setup { step: SetupStep, source: Errno },
+    /// A pre-pivot or pre-exec hook returned an error
+    Hook(Errno),
+    /// The child process reported a failure before exec,
+    /// but the report could not be decoded
+    MalformedReport,
+    /// The child process panicked before exec, which is a bug in this library
+    ChildPanic,
+    /// A system call failed
+    Sys(Errno),
+    /// An I/O operation failed
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// The underlying error number, used for reporting errors from the child
+    pub(crate) fn errno(&self) -> Errno {
+        match self {
+            Self::Mount { source, .. }
+            | Self::Exec { source, .. }
+            | Self::CurrentDir { source, .. }
+            | Self::Setup { source, .. } => *source,
+            Self::Clone(errno)
+            | Self::UserNamespace(errno)
+            | Self::Network(errno)
+            | Self::Hook(errno)
+            | Self::Sys(errno) => *errno,
+            Self::TempDir(err) | Self::Cgroup { source: err, .. } | Self::Io(err) => io_errno(err),
+            Self::CgroupUnavailable(_) => Errno::ENODEV,
+            Self::MalformedReport => Errno::EPROTO,
+            Self::ChildPanic => Errno::ENOTRECOVERABLE,
+            Self::InvalidPath
+            | Self::InvalidEnv(_)
+            | Self::InvalidArg(_)
+            | Self::InvalidUtsName(_)
+            | Self::InvalidRlimit { .. } => Errno::EINVAL,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mount { path, source } => {
+                write!(f, "Could not mount {}: {}", path.display(), source.desc())
+            }
+            Self::Clone(errno) => write!(f, "Could not create child process: {}", errno.desc()),
+            Self::UserNamespace(errno) => {
+                write!(f, "Could not set up user namespace: {}", errno.desc())
+            }
+            Self::Exec { path, source } => {
+                write!(f, "Could not execute {}: {}", path.display(), source.desc())
+            }
+            Self::CurrentDir { path, source } => write!(
+                f,
+                "Could not change directory to {}: {}",
+                path.display(),
+                source.desc()
+            ),
+            Self::TempDir(err) => write!(f, "Could not create temporary directory: {}", err),
+            Self::Cgroup { path, source } => {
+                write!(
+                    f,
+                    "Could not configure cgroup {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+            Self::CgroupUnavailable(path) => {
+                write!(f, "Cgroup v2 is not mounted at {}", path.display())
+            }
+            Self::Network(errno) => write!(f, "Could not configure network: {}", errno.desc()),
+            Self::InvalidPath => write!(f, "Invalid path"),
+            Self::InvalidEnv(key) => write!(f, "Invalid environment variable {:?}", key),
+            Self::InvalidArg(arg) => write!(f, "Invalid argument {:?}", arg),
+            Self::InvalidUtsName(name) => write!(f, "Invalid host or domain name {:?}", name),
+            Self::InvalidRlimit {
+                resource,
+                soft,
+                hard,
+            } => write!(
+                f,
+                "Soft limit {} of {:?} is above the hard limit {}",
+                soft, resource, hard
+            ),
+            Self::Setup { step, source } => {
+                write!(f, "Container setup failed, {}: {}", step, source.desc())
+            }
+            Self::Hook(errno) => write!(f, "Hook failed: {}", errno.desc()),
+            Self::MalformedReport => write!(f, "Malformed error report from the child process"),
+            Self::ChildPanic => write!(f, "BUG: the child process panicked before exec"),
+            Self::Sys(errno) => write!(f, "{}", errno.desc()),
+            Self::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Mount { source, .. }
+            | Self::Exec { source, .. }
+            | Self::CurrentDir { source, .. }
+            | Self::Setup { source, .. } => Some(source),
+            Self::Clone(errno)
+            | Self::UserNamespace(errno)
+            | Self::Network(errno)
+            | Self::Hook(errno)
+            | Self::Sys(errno) => Some(errno),
+            Self::TempDir(err) | Self::Cgroup { source: err, .. } | Self::Io(err) => Some(err),
+            Self::CgroupUnavailable(_)
+            | Self::MalformedReport
+            | Self::ChildPanic
+            | Self::InvalidPath
+            | Self::InvalidEnv(_)
+            | Self::InvalidArg(_)
+            | Self::InvalidUtsName(_)
+            | Self::InvalidRlimit { .. } => None,
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(err: nix::Error) -> Self {
+        match err {
+            nix::Error::Sys(errno) => Self::Sys(errno),
+            nix::Error::InvalidPath | nix::Error::InvalidUtf8 => Self::InvalidPath,
+            nix::Error::UnsupportedOperation => Self::Sys(Errno::EOPNOTSUPP),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Error number of an I/O error, if it has one
+pub(crate) fn io_errno(err: &io::Error) -> Errno {
+    Errno::from_i32(err.raw_os_error().unwrap_or(0))
+}
+
+/// Step of the container setup that failed in the child process, see `Error::Setup`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetupStep {
+    /// Waiting for the parent to configure the user namespace or the cgroup
+    Sync,
+    /// Setting the hostname or the domain name
+    Uts,
+    /// Bringing up the loopback interface
+    Loopback,
+    /// Configuring the veth interface of the container
+    Veth,
+    /// Mounting the overlay filesystem or the single layer inside a user namespace
+    OverlayMount,
+    /// Opening the old or the new root directory
+    OpenRoot,
+    /// Making the old and the new root private mounts
+    MountPrivate,
+    /// Binding the sysfs of the host
+    BindSysfs,
+    /// Creating `/dev` and binding device nodes
+    MountDev,
+    /// Binding a host path into the container
+    BindMount,
+    /// Changing the root directory
+    PivotRoot,
+    /// Mounting `/proc`
+    MountProc,
+    /// Mounting `/sys`
+    MountSysfs,
+    /// Mounting `/dev/shm`
+    MountShm,
+    /// Mounting a tmpfs
+    MountTmpfs,
+    /// Hiding a path or making it read-only
+    MaskPath,
+    /// Detaching the old root directory
+    UmountOldRoot,
+    /// Making the root directory read-only
+    ReadOnlyRoot,
+    /// Redirecting the standard streams
+    Stdio,
+    /// Setting a resource limit
+    Rlimit,
+    /// Changing the user and the groups
+    Credentials,
+    /// Dropping capabilities
+    Capabilities,
+    /// Forking the program from the init process
+    Init,
+    /// Setting `no_new_privs`
+    NoNewPrivs,
+    /// Installing the seccomp filter
+    Seccomp,
+}
+
+impl SetupStep {
+    /// All steps in declaration order, indexed by their encoded value
+    const ALL: [Self; 25] = [
+        Self::Sync,
+        Self::Uts,
+        Self::Loopback,
+        Self::Veth,
+        Self::OverlayMount,
+        Self::OpenRoot,
+        Self::MountPrivate,
+        Self::BindSysfs,
+        Self::MountDev,
+        Self::BindMount,
+        Self::PivotRoot,
+        Self::MountProc,
+        Self::MountSysfs,
+        Self::MountShm,
+        Self::MountTmpfs,
+        Self::MaskPath,
+        Self::UmountOldRoot,
+        Self::ReadOnlyRoot,
+        Self::Stdio,
+        Self::Rlimit,
+        Self::Credentials,
+        Self::Capabilities,
+        Self::Init,
+        Self::NoNewPrivs,
+        Self::Seccomp,
+    ];
+}
+
+impl fmt::Display for SetupStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let desc = match self {
+            Self::Sync => "waiting for the parent process",
+            Self::Uts => "setting the hostname",
+            Self::Loopback => "bringing up the loopback interface",
+            Self::Veth => "configuring the veth interface",
+            Self::OverlayMount => "mounting the overlay filesystem",
+            Self::OpenRoot => "opening the root directory",
+            Self::MountPrivate => "making the mounts private",
+            Self::BindSysfs => "binding /sys of the host",
+            Self::MountDev => "creating /dev",
+            Self::BindMount => "binding a host path",
+            Self::PivotRoot => "changing the root directory",
+            Self::MountProc => "mounting /proc",
+            Self::MountSysfs => "mounting /sys",
+            Self::MountShm => "mounting /dev/shm",
+            Self::MountTmpfs => "mounting a tmpfs",
+            Self::MaskPath => "masking a path",
+            Self::UmountOldRoot => "detaching the old root directory",
+            Self::ReadOnlyRoot => "making the root directory read-only",
+            Self::Stdio => "redirecting standard streams",
+            Self::Rlimit => "setting resource limits",
+            Self::Credentials => "changing the user",
+            Self::Capabilities => "dropping capabilities",
+            Self::Init => "starting the init process",
+            Self::NoNewPrivs => "setting no_new_privs",
+            Self::Seccomp => "installing the seccomp filter",
+        };
+        f.write_str(desc)
+    }
+}
+
+/// Attaches the failed setup step to an error in the child process
+pub(crate) trait SetupContext<T> {
+    fn step(self, step: SetupStep) -> std::result::Result<T, ChildError>;
+}
+
+impl<T, E: Into<Error>> SetupContext<T> for std::result::Result<T, E> {
+    fn step(self, step: SetupStep) -> std::result::Result<T, ChildError> {
+        self.map_err(|err| ChildError::Setup(step, err.into().errno()))
+    }
+}
+
+/// Failure in the child process before exec.
+/// Sent to the parent over a pipe as a fixed-size message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ChildError {
+    Setup(SetupStep, Errno),
+    CurrentDir(Errno),
+    Exec(Errno),
+    Hook(Errno),
+    /// Written by the panic hook, the message is printed by the child
+    Panic,
+}
+
+impl ChildError {
+    /// Size of an encoded message in bytes
+    pub(crate) const SIZE: usize = 12;
+
+    pub(crate) fn encode(self) -> [u8; Self::SIZE] {
+        // The step is only meaningful for setup errors
+        let (kind, step, errno): (u32, u32, _) = match self {
+            Self::Setup(step, errno) => (0, step as u32, errno),
+            Self::Exec(errno) => (1, 0, errno),
+            Self::CurrentDir(errno) => (2, 0, errno),
+            Self::Hook(errno) => (3, 0, errno),
+            Self::Panic => (4, 0, Errno::UnknownErrno),
+        };
+
+        let mut msg = [0; Self::SIZE];
+        msg[..4].copy_from_slice(&kind.to_ne_bytes());
+        msg[4..8].copy_from_slice(&step.to_ne_bytes());
+        msg[8..].copy_from_slice(&(errno as i32).to_ne_bytes());
+        msg
+    }
+
+    /// Returns `None` if the message is malformed
+    pub(crate) fn decode(msg: &[u8]) -> Option<Self> {
+        if msg.len() != Self::SIZE {
+            return None;
+        }
+        let mut kind = [0; 4];
+        let mut step = [0; 4];
+        let mut errno = [0; 4];
+        kind.copy_from_slice(&msg[..4]);
+        step.copy_from_slice(&msg[4..8]);
+        errno.copy_from_slice(&msg[8..]);
+        let errno = Errno::from_i32(i32::from_ne_bytes(errno));
+        match u32::from_ne_bytes(kind) {
+            0 => {
+                let step = SetupStep::ALL.get(u32::from_ne_bytes(step) as usize)?;
+                Some(Self::Setup(*step, errno))
+            }
+            1 => Some(Self::Exec(errno)),
+            2 => Some(Self::CurrentDir(errno)),
+            3 => Some(Self::Hook(errno)),
+            4 => Some(Self::Panic),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::errno::Errno;
+
+    use super::{ChildError, SetupStep};
+
+    #[test]
+    fn steps_in_declaration_order() {
+        for (i, step) in SetupStep::ALL.iter().enumerate() {
+            assert_eq!(*step as usize, i, "{:?} is out of order", step);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for &step in SetupStep::ALL.iter() {
+            let error = ChildError::Setup(step, Errno::EPERM);
+            assert_eq!(ChildError::decode(&error.encode()), Some(error));
+        }
+        for error in [
+            ChildError::CurrentDir(Errno::ENOENT),
+            ChildError::Exec(Errno::EACCES),
+            ChildError::Hook(Errno::EINVAL),
+            ChildError::Panic,
+        ] {
+            assert_eq!(ChildError::decode(&error.encode()), Some(error));
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(ChildError::decode(b"short"), None);
+        let mut msg = ChildError::Exec(Errno::EPERM).encode();
+        msg[..4].copy_from_slice(&u32::MAX.to_ne_bytes());
+        assert_eq!(ChildError::decode(&msg), None);
+        let mut msg = ChildError::Setup(SetupStep::Sync, Errno::EPERM).encode();
+        msg[4..8].copy_from_slice(&(SetupStep::ALL.len() as u32).to_ne_bytes());
+        assert_eq!(ChildError::decode(&msg), None);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use nix::errno::Errno;
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+
+/// Signals forwarded from the init process to the program
+const FORWARDED_SIGNALS: [Signal; 8] = [
+    Signal::SIGHUP,
+    Signal::SIGINT,
+    Signal::SIGQUIT,
+    Signal::SIGTERM,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGWINCH,
+    Signal::SIGCONT,
+];
+
+/// Process id of the program, for the signal handler
+static PROGRAM: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signum: libc::c_int) {
+    // Only calls async-signal-safe functions
+    unsafe { libc::kill(PROGRAM.load(Ordering::SeqCst), signum) };
+}
+
+/// Detaches the init process from the files of the parent, before `run`.
+/// Stdio is pointed at `/dev/null`, and every other descriptor is closed,
+/// including the report pipe and any pipes inherited from other spawns, so
+/// that no pipe is held open until the program exits.
+pub(crate) fn release_fds() {
+    let null = unsafe { libc::open(b"/dev/null\0".as_ptr().cast(), libc::O_RDWR) };
+    for fd in 0..3 {
+        if null >= 0 {
+            unsafe { libc::dup2(null, fd) };
+        } else {
+            unsafe { libc::close(fd) };
+        }
+    }
+
+    // close_range requires Linux 5.9, otherwise every possible fd is closed
+    let res = unsafe { libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) };
+    if res != 0 {
+        let max = unsafe { libc::sysconf(libc::_SC_OPEN_MAX) };
+        for fd in 3..max.max(1024) as libc::c_int {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
+
+/// Runs as PID 1 of the container, see `Command::use_init`.
+/// Reaps all orphaned processes until the program exits, and then returns its
+/// exit code, or 128 plus the signal number if it was killed by a signal.
+pub(crate) fn run(program: Pid) -> isize {
+    PROGRAM.store(program.as_raw(), Ordering::SeqCst);
+    for &sig in &FORWARDED_SIGNALS {
+        // Without a handler, PID 1 ignores signals sent from inside the namespace
+        let _ = unsafe { signal(sig, SigHandler::Handler(forward_signal)) };
+    }
+
+    loop {
+        match waitpid(Pid::from_raw(-1), None) {
+            Ok(WaitStatus::Exited(pid, code)) if pid == program => return code as isize,
+            Ok(WaitStatus::Signaled(pid, sig, _)) if pid == program => return 128 + sig as isize,
+            // An orphan was reaped
+            Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+            // No children left, which should not happen before the program exits
+            Err(_) => return 127,
+        }
+    }
+}
@@ -1,23 +1,71 @@
+use std::cell::Cell;
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use backtrace::Backtrace;
-
+use nix::errno::Errno;
 use nix::fcntl::OFlag;
 use nix::sched::{clone, CloneFlags};
-use nix::sys::signal::Signal;
-use nix::sys::wait::waitpid;
-use nix::unistd::{execv, mkdir, Pid};
+use nix::sys::signal::{signal, SigHandler, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag};
+use nix::unistd::{
+    chdir, dup2, execve, fork, getgid, getuid, mkdir, pipe2, setgid, setgroups, sethostname,
+    setuid, write, ForkResult, Gid, Pid, Uid,
+};
 
 use tempfile::{tempdir, TempDir};
 
+mod capability;
+mod cgroup;
 mod command;
+mod error;
+mod init;
+mod net;
+mod rlimit;
+mod sandbox;
+mod seccomp;
+mod status;
+mod stdio;
+mod sync;
+mod trace;
+mod wait;
 
-use command::DiskWritePolicy;
+use cgroup::Cgroup;
+use command::{BindMount, DiskWritePolicy, IdMaps, Mount, TmpfsMount};
+use error::{io_errno, ChildError, SetupContext};
+use net::Veth;
+use sync::{wait_for_parent, SyncPipe};
+use trace::{event, Span};
 
 // Re-exports
-pub use self::command::Command;
+pub use self::capability::Capability;
+pub use self::command::{Command, Hook};
+pub use self::error::{Error, Result, SetupStep};
+pub use self::net::NetworkPolicy;
+pub use self::rlimit::{Resource, RLIM_INFINITY};
+pub use self::sandbox::{Sandbox, SandboxBuilder};
+pub use self::seccomp::{SeccompAction, SeccompPolicy, Syscall};
+pub use self::status::ExitStatus;
+pub use self::stdio::{ChildStderr, ChildStdin, ChildStdout, Stdio};
+pub use self::wait::WaitFuture;
 pub use nix::sys::wait::WaitStatus;
 
+/// Time between `SIGTERM` and `SIGKILL` in `Process::shutdown` unless configured
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+thread_local! {
+    /// Report pipe of the child being spawned by this thread, for the panic hook.
+    /// The child shares the thread-local storage of the thread that cloned it,
+    /// while the hook itself may have been replaced by a spawn on another thread.
+    static REPORT_FD: Cell<RawFd> = const { Cell::new(-1) };
+}
+
 /// Wrapper for automatically closing a raw file
 /// when it goes out of scope
 struct AutoCloseFd {
@@ -31,8 +79,247 @@ impl Drop for AutoCloseFd {
         let _ = close(self.fd);
     }
 }
+impl AutoCloseFd {
+    /// Transfers the ownership of the file descriptor to a `File`
+    fn into_file(self) -> File {
+        let fd = self.fd;
+        std::mem::forget(self);
+        unsafe { File::from_raw_fd(fd) }
+    }
+}
+
+/// Stack of the child process before exec, with a guard page below it,
+/// so that an overflow terminates the child instead of corrupting its memory
+struct CloneStack {
+    map: *mut libc::c_void,
+    len: usize,
+    guard: usize,
+}
+
+impl CloneStack {
+    fn new(size: usize) -> nix::Result<Self> {
+        use nix::sys::mman::{mmap, mprotect, MapFlags, ProtFlags};
+
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = page + size.div_ceil(page) * page;
+        let flags = MapFlags::MAP_PRIVATE | MapFlags::MAP_ANONYMOUS | MapFlags::MAP_STACK;
+        let prot = ProtFlags::PROT_READ | ProtFlags::PROT_WRITE;
+        let map = unsafe { mmap(std::ptr::null_mut(), len, prot, flags, -1, 0) }?;
+        // Unmapped on drop if the guard page cannot be set up
+        let stack = Self {
+            map,
+            len,
+            guard: page,
+        };
+        // The stack grows down, towards the guard page
+        unsafe { mprotect(map, page, ProtFlags::PROT_NONE) }?;
+        Ok(stack)
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            let start = (self.map as *mut u8).add(self.guard);
+            std::slice::from_raw_parts_mut(start, self.len - self.guard)
+        }
+    }
+}
+
+impl Drop for CloneStack {
+    fn drop(&mut self) {
+        use nix::sys::mman::munmap;
+        // Ignore errors
+        let _ = unsafe { munmap(self.map, self.len) };
+    }
+}
+
+/// Binds a host path into the new root filesystem, creating the target if needed
+fn bind_mount(root: &Path, bind: &BindMount) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+
+    let none: Option<&str> = None;
+    let target = root.join(bind.target.strip_prefix("/").unwrap_or(&bind.target));
+    if std::fs::metadata(&bind.source)?.is_dir() {
+        std::fs::create_dir_all(&target)?;
+    } else if !target.exists() {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        File::create(&target)?;
+    }
+
+    let flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+    mount(Some(&bind.source), &target, none, flags, none)?;
+
+    if bind.read_only {
+        remount_read_only(&target)?;
+    }
+    Ok(())
+}
+
+/// Mounts a tmpfs at the target inside the new root, creating the directory
+fn tmpfs_mount(root: &Path, tmpfs: &TmpfsMount) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+
+    let target = root.join(tmpfs.target.strip_prefix("/").unwrap_or(&tmpfs.target));
+    std::fs::create_dir_all(&target)?;
+    mount(
+        Some("tmpfs"),
+        &target,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(format!("size={}", tmpfs.size).as_str()),
+    )?;
+    Ok(())
+}
+
+/// Makes an existing bind mount read-only
+fn remount_read_only(target: &Path) -> Result<()> {
+    remount_bind(target, nix::mount::MsFlags::MS_RDONLY)
+}
+
+/// Adds flags to an existing bind mount
+fn remount_bind(target: &Path, extra: nix::mount::MsFlags) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+    use nix::sys::statvfs::{statvfs, FsFlags};
+
+    let none: Option<&str> = None;
+    // Locked flags of the source mount must be kept when remounting
+    let mut flags = MsFlags::MS_BIND | MsFlags::MS_REMOUNT | extra;
+    let source_flags = statvfs(target)?.flags();
+    for &(fs_flag, ms_flag) in &[
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ] {
+        if source_flags.contains(fs_flag) {
+            flags |= ms_flag;
+        }
+    }
+    mount(none, target, none, flags, none)?;
+    Ok(())
+}
+
+/// Paths of the kernel interface that are always read-only in the container
+const READ_ONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Hides a file or a directory, if it exists, see `Command::mask_path`
+fn mask_path(path: &Path) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+
+    let none: Option<&str> = None;
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    if metadata.is_dir() {
+        let flags =
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+        mount(Some("tmpfs"), path, Some("tmpfs"), flags, Some("mode=555"))?;
+    } else {
+        mount(Some("/dev/null"), path, none, MsFlags::MS_BIND, none)?;
+    }
+    Ok(())
+}
+
+/// Makes a path read-only, if it exists
+fn read_only_path(path: &Path) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+
+    let none: Option<&str> = None;
+    if !path.exists() {
+        return Ok(());
+    }
+    mount(
+        Some(path),
+        path,
+        none,
+        MsFlags::MS_BIND | MsFlags::MS_REC,
+        none,
+    )?;
+    remount_read_only(path)
+}
+
+/// Device nodes bound from the host by `setup_dev`
+const DEV_NODES: &[&str] = &["null", "zero", "full", "random", "urandom", "tty"];
+
+/// Creates a minimal `/dev` in the new root filesystem. Device nodes are
+/// bound from the host, as `mknod` is not permitted in a user namespace.
+fn setup_dev(root: &Path, dev_pts: bool) -> Result<()> {
+    use nix::mount::{mount, MsFlags};
+    use std::os::unix::fs::symlink;
+
+    let none: Option<&str> = None;
+    let dev = root.join("dev");
+    std::fs::create_dir_all(&dev)?;
+    mount(
+        Some("tmpfs"),
+        &dev,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+        Some("mode=755"),
+    )?;
+
+    for name in DEV_NODES {
+        let source = Path::new("/dev").join(name);
+        // For instance /dev/tty may be missing without a terminal
+        if !source.exists() {
+            continue;
+        }
+        let target = dev.join(name);
+        File::create(&target)?;
+        mount(Some(&source), &target, none, MsFlags::MS_BIND, none)?;
+    }
+
+    symlink("/proc/self/fd", dev.join("fd"))?;
+    symlink("/proc/self/fd/0", dev.join("stdin"))?;
+    symlink("/proc/self/fd/1", dev.join("stdout"))?;
+    symlink("/proc/self/fd/2", dev.join("stderr"))?;
+
+    if dev_pts {
+        let pts = dev.join("pts");
+        std::fs::create_dir(&pts)?;
+        mount(
+            Some("devpts"),
+            &pts,
+            Some("devpts"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC,
+            Some("newinstance,ptmxmode=0666,mode=0620"),
+        )?;
+        symlink("pts/ptmx", dev.join("ptmx"))?;
+    }
+    Ok(())
+}
 
-fn setup_rootfs(path: &Path) {
+/// Mounts of the new root filesystem, see `setup_rootfs`
+struct RootfsConfig {
+    mounts: Vec<Mount>,
+    /// Whether to create a new `/dev`, and `/dev/pts` if it is `Some(true)`
+    dev: Option<bool>,
+    masked_paths: Vec<PathBuf>,
+    mount_shm: bool,
+    /// A new sysfs can only be mounted in a network namespace owned by the
+    /// current user namespace, so otherwise the one of the host is bound
+    host_sysfs: bool,
+    /// Whether the root is made read-only after the other mounts
+    read_only: bool,
+    /// Whether `/sys` is left writable, see `Command::writable_sys`
+    writable_sys: bool,
+    /// Whether `/proc` is mounted with `hidepid=2`, see `Command::hide_pids`
+    hide_pids: bool,
+}
+
+/// Pivots into the new root filesystem, and mounts pseudo-filesystems there
+fn setup_rootfs(path: &Path, config: &RootfsConfig) -> std::result::Result<(), ChildError> {
     use nix::fcntl::open;
     use nix::mount::{mount, umount2, MntFlags, MsFlags};
     use nix::sys::stat::Mode;
@@ -44,88 +331,538 @@ fn setup_rootfs(path: &Path) {
 
     // Hold both old and new root file descriptors
     AutoCloseFd {
-        fd: open("/", oflag, mode).expect("Could not open old root directory"),
+        fd: open("/", oflag, mode).step(SetupStep::OpenRoot)?,
     };
     let newroot = AutoCloseFd {
-        fd: open(path, oflag, mode).expect("Could not open new root directory"),
+        fd: open(path, oflag, mode).step(SetupStep::OpenRoot)?,
     };
 
     // Mark old and new roots as private
-    mount(none, "/", none, MsFlags::MS_PRIVATE, none)
-        .expect("Could not remount old root directory as private");
-    mount(none, path, none, MsFlags::MS_PRIVATE, none)
-        .expect("Could not remount new root directory as private");
+    mount(none, "/", none, MsFlags::MS_PRIVATE, none).step(SetupStep::MountPrivate)?;
+    mount(none, path, none, MsFlags::MS_PRIVATE, none).step(SetupStep::MountPrivate)?;
+
+    if config.host_sysfs {
+        let target = path.join("sys");
+        let _ = mkdir(&target, Mode::from_bits(0o700).unwrap());
+        let flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+        mount(Some("/sys"), &target, none, flags, none).step(SetupStep::BindSysfs)?;
+        if !config.writable_sys {
+            remount_read_only(&target).step(SetupStep::BindSysfs)?;
+        }
+    }
+
+    // Host paths are not reachable after pivot_root
+    if let Some(dev_pts) = config.dev {
+        setup_dev(path, dev_pts).step(SetupStep::MountDev)?;
+    }
+
+    // Shared memory of the host is not visible in a new IPC namespace,
+    // and is not bound to a new /dev either
+    if config.mount_shm {
+        let _ = mkdir(&path.join("dev"), Mode::from_bits(0o755).unwrap());
+        let target = path.join("dev/shm");
+        let _ = mkdir(&target, Mode::from_bits(0o1777).unwrap());
+        mount(
+            Some("tmpfs"),
+            &target,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some("mode=1777"),
+        )
+        .step(SetupStep::MountShm)?;
+    }
+
+    // In registration order, so that nested mounts are deterministic
+    for mount_cfg in &config.mounts {
+        match mount_cfg {
+            Mount::Bind(bind) => bind_mount(path, bind).step(SetupStep::BindMount)?,
+            Mount::Tmpfs(tmpfs) => tmpfs_mount(path, tmpfs).step(SetupStep::MountTmpfs)?,
+        }
+    }
 
     // Change root to point to the new root directory
-    fchdir(newroot.fd).expect("Chould not change to new root directory");
-    pivot_root(".", ".").expect("pivot_root failed");
+    fchdir(newroot.fd).step(SetupStep::PivotRoot)?;
+    pivot_root(".", ".").step(SetupStep::PivotRoot)?;
 
     // Mount useful pseudo-filesystems
     let _ = mkdir("/proc", Mode::from_bits(0o700).unwrap());
-    mount(none, "/proc", Some("proc"), MsFlags::empty(), none).expect("Could not mount proc");
+    let proc_data = if config.hide_pids {
+        Some("hidepid=2")
+    } else {
+        None
+    };
+    mount(none, "/proc", Some("proc"), MsFlags::empty(), proc_data).step(SetupStep::MountProc)?;
 
-    let _ = mkdir("/sys", Mode::from_bits(0o700).unwrap());
-    mount(none, "/sys", Some("sysfs"), MsFlags::empty(), none).expect("Could not mount sysfs");
+    if !config.host_sysfs {
+        let _ = mkdir("/sys", Mode::from_bits(0o700).unwrap());
+        let mut flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+        if !config.writable_sys {
+            flags |= MsFlags::MS_RDONLY;
+        }
+        mount(none, "/sys", Some("sysfs"), flags, none).step(SetupStep::MountSysfs)?;
+    }
+
+    // Kernel interfaces of the host, after the pseudo-filesystems are mounted
+    for path in READ_ONLY_PATHS {
+        read_only_path(Path::new(path)).step(SetupStep::MaskPath)?;
+    }
+    for path in &config.masked_paths {
+        mask_path(path).step(SetupStep::MaskPath)?;
+    }
 
     // Detach from the old root so that it can not be used anymore
-    umount2("/", MntFlags::MNT_DETACH).expect("Could not detach from old root directory");
+    umount2("/", MntFlags::MNT_DETACH).step(SetupStep::UmountOldRoot)?;
+
+    // Last, as mount points may need to be created in the writedir
+    if config.read_only {
+        remount_read_only(Path::new("/")).step(SetupStep::ReadOnlyRoot)?;
+    }
+    Ok(())
+}
+
+/// Redirects the standard streams of the current process to given file descriptors
+fn setup_stdio(stdin: Option<i32>, stdout: Option<i32>, stderr: Option<i32>) -> nix::Result<()> {
+    if let Some(fd) = stdin {
+        dup2(fd, 0)?;
+    }
+    if let Some(fd) = stdout {
+        dup2(fd, 1)?;
+    }
+    if let Some(fd) = stderr {
+        dup2(fd, 2)?;
+    }
+
+    // Rust runtime ignores SIGPIPE, and ignored signals are inherited over exec
+    unsafe { signal(Signal::SIGPIPE, SigHandler::SigDfl) }?;
+    Ok(())
+}
+
+/// Writes user namespace mappings of the child process.
+/// Unprivileged processes must deny setgroups before writing the GID map.
+fn write_id_maps(pid: Pid, maps: &IdMaps, deny_setgroups: bool) -> std::io::Result<()> {
+    fn format_map(map: &[(u32, u32, u32)]) -> String {
+        map.iter()
+            .map(|(inside, outside, count)| format!("{} {} {}\n", inside, outside, count))
+            .collect()
+    }
+
+    std::fs::write(format!("/proc/{}/uid_map", pid), format_map(&maps.uid_map))?;
+    if deny_setgroups {
+        std::fs::write(format!("/proc/{}/setgroups", pid), "deny")?;
+    }
+    std::fs::write(format!("/proc/{}/gid_map", pid), format_map(&maps.gid_map))?;
+    Ok(())
+}
+
+/// Changes the groups and the user of the current process, in that order.
+/// Supplementary groups are cleared when changing the user, unless specified.
+fn setup_credentials(
+    uid: Option<u32>,
+    gid: Option<u32>,
+    groups: Option<&[u32]>,
+    setgroups_denied: bool,
+) -> nix::Result<()> {
+    if let Some(groups) = groups {
+        let groups: Vec<Gid> = groups.iter().copied().map(Gid::from_raw).collect();
+        setgroups(&groups)?;
+    } else if (uid.is_some() || gid.is_some()) && !setgroups_denied {
+        // Do not keep the supplementary groups of root
+        setgroups(&[])?;
+    }
+    if let Some(gid) = gid {
+        setgid(Gid::from_raw(gid))?;
+    }
+    if let Some(uid) = uid {
+        setuid(Uid::from_raw(uid))?;
+    }
+    Ok(())
+}
+
+/// Opens a file descriptor referring to the process, not provided by nix.
+/// It becomes readable when the process terminates.
+fn pidfd_open(pid: Pid) -> nix::Result<AutoCloseFd> {
+    let res = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    Errno::result(res).map(|fd| AutoCloseFd { fd: fd as i32 })
+}
+
+/// Waits until the process of the pidfd terminates, or the deadline passes.
+/// Returns whether the process has terminated.
+fn poll_pidfd(pidfd: &AutoCloseFd, deadline: Instant) -> nix::Result<bool> {
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    let mut fds = [PollFd::new(pidfd.fd, PollFlags::POLLIN)];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        // Round up, so that the deadline has always passed on timeout
+        let millis = remaining.as_micros().div_ceil(1000);
+        match poll(&mut fds, millis.min(i32::MAX as u128) as i32) {
+            Ok(0) => return Ok(false),
+            Ok(_) => return Ok(true),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Waits for the process of the pidfd with `waitid(P_PIDFD)`, like `waitpid`.
+/// Only reports terminated processes, and `StillAlive` with `WNOHANG`.
+fn waitid_pidfd(pidfd: &AutoCloseFd, pid: Pid, nohang: bool) -> nix::Result<WaitStatus> {
+    use std::convert::TryFrom;
+
+    let mut flags = libc::WEXITED;
+    if nohang {
+        flags |= libc::WNOHANG;
+    }
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let res = unsafe { libc::waitid(libc::P_PIDFD, pidfd.fd as libc::id_t, &mut info, flags) };
+    Errno::result(res)?;
+
+    // The pid is left zero if no process has terminated yet
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(WaitStatus::StillAlive);
+    }
+    let status = unsafe { info.si_status() };
+    Ok(match info.si_code {
+        libc::CLD_EXITED => WaitStatus::Exited(pid, status),
+        code => WaitStatus::Signaled(pid, Signal::try_from(status)?, code == libc::CLD_DUMPED),
+    })
+}
+
+/// Sends a signal to the process of the pidfd, not provided by nix.
+/// Unlike `kill`, this cannot reach another process that reused the pid.
+fn pidfd_send_signal(pidfd: &AutoCloseFd, signal: Signal) -> nix::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd.fd,
+            signal as libc::c_int,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    Errno::result(res).map(drop)
+}
+
+/// Kills the process with `SIGKILL` if it is still running after the time limit.
+/// The thread completes as soon as the process terminates or is killed.
+fn spawn_watchdog(pidfd: AutoCloseFd, limit: Duration, timed_out: Arc<AtomicBool>) {
+    let deadline = Instant::now() + limit;
+    std::thread::spawn(move || {
+        if let Ok(false) = poll_pidfd(&pidfd, deadline) {
+            // Set first, so that it is visible once the process has been waited for
+            timed_out.store(true, Ordering::SeqCst);
+            let _ = pidfd_send_signal(&pidfd, Signal::SIGKILL);
+        }
+    });
+}
+
+/// Prevents exec from granting privileges, for instance with setuid binaries.
+/// Not provided by nix.
+fn set_no_new_privs() -> nix::Result<()> {
+    let res = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+    Errno::result(res).map(drop)
 }
 
-fn overlayfs_escape_path<P: Into<String>>(path: P) -> String {
-    path.into()
-        .replace("\\", "\\\\")
-        .replace(":", "\\:")
-        .replace(",", "\\,")
+/// Sets host and domain names of the current UTS namespace
+fn setup_uts(hostname: &str, domainname: Option<&str>) -> nix::Result<()> {
+    sethostname(hostname)?;
+    if let Some(name) = domainname {
+        // Not provided by nix
+        let res = unsafe { libc::setdomainname(name.as_ptr() as *const libc::c_char, name.len()) };
+        Errno::result(res)?;
+    }
+    Ok(())
+}
+
+/// Escapes the separators of overlayfs mount options in a path
+pub(crate) fn overlayfs_escape_path(path: &Path) -> Vec<u8> {
+    let mut escaped = Vec::new();
+    for &byte in path.as_os_str().as_bytes() {
+        if matches!(byte, b'\\' | b':' | b',') {
+            escaped.push(b'\\');
+        }
+        escaped.push(byte);
+    }
+    escaped
 }
 
-fn create_overlayfs(mountpoint: &Path, workdir: &Path, layers: &[PathBuf], writedir: &Path) {
+/// Mounts the root filesystem of the container. A single read-only layer is
+/// bound directly, which does not require overlayfs support in the kernel.
+/// Each writable layer is mounted on the corresponding stage, see `Stage`.
+fn mount_layers(
+    mountpoint: &Path,
+    workdir: &Path,
+    layers: &[PathBuf],
+    writable_layers: &[(PathBuf, Stage)],
+    writedir: &Path,
+    bind_layer: bool,
+    nosuid: bool,
+) -> Result<()> {
     use nix::mount::{mount, MsFlags};
 
-    let mut options = format!(
-        "workdir={}",
-        overlayfs_escape_path(workdir.to_str().expect("TODO: utf8 error"))
-    );
-    options.push_str(&format!(
-        ",lowerdir={}",
-        layers
-            .iter()
-            .map(|p| overlayfs_escape_path(p.to_str().expect("TODO: utf8 error")))
-            .collect::<Vec<_>>()
-            .join(":")
-    ));
-
-    options.push_str(&format!(
-        ",upperdir={}",
-        overlayfs_escape_path(writedir.to_str().expect("TODO: utf8 error"))
-    ));
+    let extra = if nosuid {
+        MsFlags::MS_NOSUID
+    } else {
+        MsFlags::empty()
+    };
+    if !bind_layer {
+        // Overlayfs only supports a single upper directory,
+        // so each writable layer is an overlay on top of the previous one
+        let mut lower = layers.to_vec();
+        for (layer, stage) in writable_layers {
+            create_overlayfs(&stage.mountpoint, &stage.workdir, &lower, layer, extra)?;
+            lower = vec![stage.mountpoint.clone()];
+        }
+        return create_overlayfs(mountpoint, workdir, &lower, writedir, extra);
+    }
+    let none: Option<&str> = None;
+    let layer = &layers[0];
+    let flags = MsFlags::MS_BIND | MsFlags::MS_REC;
+    mount(Some(layer), mountpoint, none, flags, none).map_err(|err| match err {
+        nix::Error::Sys(source) => Error::Mount {
+            path: layer.to_owned(),
+            source,
+        },
+        other => other.into(),
+    })?;
+    // Immediately, so that mount points are never created in the layer
+    remount_bind(mountpoint, MsFlags::MS_RDONLY | extra)
+}
+
+fn create_overlayfs(
+    mountpoint: &Path,
+    workdir: &Path,
+    layers: &[PathBuf],
+    writedir: &Path,
+    flags: nix::mount::MsFlags,
+) -> Result<()> {
+    use nix::mount::mount;
+
+    // The overlay mount only reports a single error for all directories,
+    // so check them first to find out which one is invalid
+    for dir in layers.iter().map(PathBuf::as_path).chain(Some(writedir)) {
+        if let Err(err) = std::fs::metadata(dir) {
+            return Err(Error::Mount {
+                path: dir.to_owned(),
+                source: io_errno(&err),
+            });
+        }
+    }
+
+    // Paths are not required to be UTF-8, so the options are built as bytes
+    let mut options = b"workdir=".to_vec();
+    options.extend(overlayfs_escape_path(workdir));
+
+    options.extend(b",lowerdir=");
+    let lowerdirs: Vec<_> = layers.iter().map(|p| overlayfs_escape_path(p)).collect();
+    options.extend(lowerdirs.join(&b':'));
+
+    options.extend(b",upperdir=");
+    options.extend(overlayfs_escape_path(writedir));
 
     mount(
         Some("overlay"),
         mountpoint,
         Some("overlay"),
-        MsFlags::empty(),
-        Some(options.as_str()),
+        flags,
+        Some(options.as_slice()),
     )
-    .expect("overlayfs mount");
+    .map_err(|err| match err {
+        nix::Error::Sys(source) => Error::Mount {
+            path: mountpoint.to_owned(),
+            source,
+        },
+        // Nul bytes in paths
+        other => other.into(),
+    })
+}
+
+/// Directories of an intermediate overlay, see `Command::writable_layer`
+struct Stage {
+    mountpoint: PathBuf,
+    workdir: PathBuf,
 }
 
 /// Resources held by a process.
 /// These require cleanup when the process has completed.
 #[allow(dead_code)] // Fields are used for Drop, rustc isn't smart enough
 struct HeldResources {
-    /// Deleted on drop
-    tmp: TempDir,
+    /// Deleted on drop, unless kept with `Process::into_writedir`
+    tmp: Option<TempDir>,
+    /// Merged root filesystem of the container, inside `tmp`
+    mountpoint: PathBuf,
+    /// Upper layer of the overlay
+    writedir: PathBuf,
+    /// Mountpoints of the writable layers below `mountpoint`, innermost first
+    stages: Vec<PathBuf>,
+    /// Whether the overlay is mounted in the host mount namespace
+    mounted: bool,
+    /// Removed on drop, after the process has exited
+    cgroup: Option<Cgroup>,
+    /// Host end of the veth pair, deleted on drop
+    veth: Option<Veth>,
+}
+
+impl HeldResources {
+    /// Mounts of the root filesystem, in the order they must be unmounted
+    fn mounts(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.mountpoint).chain(self.stages.iter().rev())
+    }
+
+    /// Unmounts the root filesystem from the host mount namespace
+    fn unmount(&mut self) -> nix::Result<()> {
+        use nix::mount::umount;
+
+        if self.mounted {
+            for path in self.mounts() {
+                umount(path)?;
+            }
+            self.mounted = false;
+        }
+        Ok(())
+    }
 }
 
 impl Drop for HeldResources {
     fn drop(&mut self) {
-        let mountpoint = self.tmp.path().join("mount");
-        nix::mount::umount(&mountpoint).expect("Failed to umount mountpoint");
+        use nix::mount::{umount2, MntFlags};
+
+        // The mount may still be busy briefly, so detach it lazily if needed.
+        // Errors are ignored, as nothing else can be done here.
+        if self.unmount().is_err() {
+            for path in self.mounts() {
+                let _ = umount2(path, MntFlags::MNT_DETACH);
+            }
+        }
     }
 }
 
+/// Temporary directories of the root filesystem of a container,
+/// with the overlay mounted if it is done in the host mount namespace
+pub(crate) struct PreparedRoot {
+    resources: HeldResources,
+    /// Work directory of the outermost overlay, inside the temporary directory
+    workdir: PathBuf,
+    /// Read-only layers, from innermost to outermost
+    layers: Vec<PathBuf>,
+    writable_layers: Vec<(PathBuf, Stage)>,
+    /// Whether the single read-only layer is bind mounted instead of an overlay
+    bind_layer: bool,
+    /// Whether setuid bits are ignored, see `Command::nosuid`
+    nosuid: bool,
+}
+
+impl PreparedRoot {
+    pub(crate) fn new(
+        layers: Vec<PathBuf>,
+        disk_write: DiskWritePolicy,
+        writable_layers: Vec<PathBuf>,
+        force_overlayfs: bool,
+        nosuid: bool,
+        mounted: bool,
+    ) -> Result<Self> {
+        let tmp = tempdir().map_err(Error::TempDir)?;
+        let mountpoint = tmp.path().join("mount");
+        let workdir = tmp.path().join("work");
+
+        let read_only = matches!(disk_write, DiskWritePolicy::ReadOnly);
+        let writedir = match disk_write {
+            DiskWritePolicy::TempDir | DiskWritePolicy::ReadOnly => {
+                let d = tmp.path().join("write");
+                std::fs::create_dir(&d).map_err(Error::TempDir)?;
+                d
+            }
+            DiskWritePolicy::WriteDir(d) => d,
+        };
+
+        std::fs::create_dir(&mountpoint).map_err(Error::TempDir)?;
+        std::fs::create_dir(&workdir).map_err(Error::TempDir)?;
+
+        let mut stages = Vec::new();
+        for (i, layer) in writable_layers.into_iter().enumerate() {
+            let dir = tmp.path().join(format!("stage{}", i));
+            let stage = Stage {
+                mountpoint: dir.join("mount"),
+                workdir: dir.join("work"),
+            };
+            std::fs::create_dir_all(&stage.mountpoint).map_err(Error::TempDir)?;
+            std::fs::create_dir_all(&stage.workdir).map_err(Error::TempDir)?;
+            stages.push((layer, stage));
+        }
+
+        let bind_layer = read_only && !force_overlayfs && layers.len() == 1 && stages.is_empty();
+        // Created first, so that a partially mounted chain is cleaned up on failure
+        let resources = HeldResources {
+            tmp: Some(tmp),
+            mountpoint: mountpoint.clone(),
+            writedir: writedir.clone(),
+            stages: stages
+                .iter()
+                .map(|(_, stage)| stage.mountpoint.clone())
+                .collect(),
+            mounted,
+            cgroup: None,
+            veth: None,
+        };
+        if mounted {
+            mount_layers(
+                &mountpoint,
+                &workdir,
+                &layers,
+                &stages,
+                &writedir,
+                bind_layer,
+                nosuid,
+            )?;
+            event!(debug, mountpoint = ?mountpoint, "mounted the root filesystem");
+        }
+        Ok(Self {
+            resources,
+            workdir,
+            layers,
+            writable_layers: stages,
+            bind_layer,
+            nosuid,
+        })
+    }
+
+    /// Whether the command can be spawned in this root, which has the default
+    /// write policy, no writable layers, and is mounted by the parent
+    pub(crate) fn matches(&self, command: &Command) -> bool {
+        self.resources.mounted
+            && user_namespace(command).is_none()
+            && self.layers == command.layers
+            && matches!(command.disk_write, DiskWritePolicy::TempDir)
+            && command.writable_layers.is_empty()
+            && self.nosuid == command.nosuid
+    }
+}
+
+/// The user namespace the command is spawned in, if any.
+/// Without root privileges, namespaces can only be created in a user namespace,
+/// so one is added where the requested user and group are the current ones.
+fn user_namespace(command: &Command) -> Option<IdMaps> {
+    let (uid, gid) = (command.uid, command.gid);
+    command.user_namespace.clone().or_else(|| {
+        let needs_mapping = uid.is_some() || gid.is_some();
+        (needs_mapping && !Uid::effective().is_root()).then(|| IdMaps {
+            uid_map: vec![(uid.unwrap_or(0), getuid().as_raw(), 1)],
+            gid_map: vec![(gid.unwrap_or(0), getgid().as_raw(), 1)],
+        })
+    })
+}
+
+/// How the number of processes is limited, see `Command::max_pids`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PidLimitMethod {
+    /// `pids.max` of a cgroup v2, counting the processes of the container
+    Cgroup,
+    /// `RLIMIT_NPROC`, counting all processes of the user on the host
+    Rlimit,
+}
+
 /// Offers an API similar to `std::process::Child`.
-/// When dropping, attempts termination and cleanup.
+/// When dropping, kills the process if it is still running, and cleans up.
 pub struct Process {
     /// A Linux process id.
     /// Only guarantedd to point to the correct existing process
@@ -133,120 +870,779 @@ pub struct Process {
     /// this must not be used anymore.
     id: Pid,
     /// Stored after the first successful `wait` call
-    status: Option<WaitStatus>,
+    status: Option<ExitStatus>,
     /// Resources, mostly stored for cleanup
-    #[allow(dead_code)] // Fields is used for Drop, rustc isn't smart enough
     resources: HeldResources,
+    /// Panic when dropped before waiting, instead of killing the process
+    must_wait: bool,
+    /// Used by `shutdown`, and when dropped if set
+    grace_period: Option<Duration>,
+    /// Mechanism used for `Command::max_pids`
+    pid_limit_method: Option<PidLimitMethod>,
+    /// Set by the watchdog thread when the time limit has been exceeded
+    time_limit_exceeded: Arc<AtomicBool>,
+    /// Refers to the process even after the pid has been reused.
+    /// `None` on kernels older than 5.3.
+    pidfd: Option<AutoCloseFd>,
+    /// Carries the path and the pid, for the `tracing` feature
+    span: Span,
+    /// Writing end of the stdin pipe, if `Stdio::Piped` was used
+    pub stdin: Option<ChildStdin>,
+    /// Reading end of the stdout pipe, if `Stdio::Piped` was used.
+    /// If both stdout and stderr are piped, reading them one after another
+    /// can deadlock when the process fills the other pipe buffer;
+    /// `output` reads them concurrently.
+    pub stdout: Option<ChildStdout>,
+    /// Reading end of the stderr pipe, if `Stdio::Piped` was used
+    pub stderr: Option<ChildStderr>,
+}
+
+/// Output of a completed process, see `Command::output` and `Process::output`
+#[derive(Debug, Clone)]
+pub struct Output {
+    /// Exit status of the process
+    pub status: ExitStatus,
+    /// Data read from stdout, empty unless `Stdio::Piped` was used
+    pub stdout: Vec<u8>,
+    /// Data read from stderr, empty unless `Stdio::Piped` was used
+    pub stderr: Vec<u8>,
 }
 
 impl Process {
     /// Spawns a new process as specified by command.
-    pub fn spawn(command: Command) -> nix::Result<Process> {
-        let tmp = tempdir().expect("tempdir creation failed");
-        let mountpoint = tmp.path().join("mount");
-        let workdir = tmp.path().join("work");
+    pub fn spawn(command: Command) -> Result<Process> {
+        Self::spawn_in(command, None)
+    }
 
-        let writedir = match command.disk_write {
-            DiskWritePolicy::TempDir => {
-                let d = tmp.path().join("write");
-                std::fs::create_dir(&d).expect("Creating temp writedir failed");
-                d
+    /// Spawns the process, using an already mounted root filesystem if possible
+    pub(crate) fn spawn_in(command: Command, prepared: Option<PreparedRoot>) -> Result<Process> {
+        let span = Span::process(&command.path);
+        span.in_scope(|| {
+            let result = Self::spawn_traced(command, prepared, span.clone());
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &result {
+                tracing::error!(error = %err, "spawning failed");
             }
-            DiskWritePolicy::WriteDir(d) => d,
+            result
+        })
+    }
+
+    fn spawn_traced(
+        command: Command,
+        prepared: Option<PreparedRoot>,
+        span: Span,
+    ) -> Result<Process> {
+        let args = command.arg_strings()?;
+        let env = command.env_strings()?;
+        command.validate_rlimits()?;
+        command.validate_uts_names()?;
+        let mut stdin = command.stdin.setup(true)?;
+        let stdout = command.stdout.setup(false)?;
+        let stderr = command.stderr.setup(false)?;
+        let stdin_fd = stdin.child.as_ref().map(|c| c.fd);
+        let stdout_fd = stdout.child.as_ref().map(|c| c.fd);
+        let stderr_fd = stderr.child.as_ref().map(|c| c.fd);
+
+        let (uid, gid) = (command.uid, command.gid);
+        let user_namespace = user_namespace(&command);
+
+        // With a user namespace, the overlay is mounted by the child, so that
+        // root privileges on the host are not required
+        let mounted = user_namespace.is_none();
+        let deny_setgroups = user_namespace.is_some() && !Uid::effective().is_root();
+        let read_only = matches!(command.disk_write, DiskWritePolicy::ReadOnly);
+        // A root from a `Sandbox` pool is only usable if it matches the command
+        let root = match prepared {
+            Some(root) if root.matches(&command) => root,
+            _ => PreparedRoot::new(
+                command.layers,
+                command.disk_write,
+                command.writable_layers,
+                command.force_overlayfs,
+                command.nosuid,
+                mounted,
+            )?,
+        };
+        let PreparedRoot {
+            mut resources,
+            workdir,
+            layers,
+            writable_layers,
+            bind_layer,
+            nosuid,
+        } = root;
+        let mountpoint = resources.mountpoint.clone();
+        let writedir = resources.writedir.clone();
+
+        // Without cgroup v2, the number of processes is limited per user instead
+        let mut cgroup_config = command.cgroup;
+        let mut rlimits = command.rlimits;
+        let pid_limit_method = command.max_pids.map(|max| {
+            if cgroup::is_available(&cgroup_config.base) {
+                cgroup_config.pids_max = Some(max);
+                PidLimitMethod::Cgroup
+            } else {
+                rlimits.retain(|(resource, _, _)| *resource != Resource::Processes);
+                rlimits.push((Resource::Processes, max, max));
+                PidLimitMethod::Rlimit
+            }
+        });
+
+        let veth = match command.network {
+            NetworkPolicy::Veth {
+                host_addr,
+                container_addr,
+                prefix_len,
+                enable_nat,
+            } => Some((host_addr, container_addr, prefix_len, enable_nat)),
+            _ => None,
         };
 
-        std::fs::create_dir(&mountpoint).expect("Creating temp mountpoint failed");
-        std::fs::create_dir(&workdir).expect("Creating temp workdir failed");
+        // The child waits on this pipe until the parent has written the id maps,
+        // moved it into its cgroup and created the veth pair, so that the limits
+        // apply and the network is ready before exec
+        let needs_sync = user_namespace.is_some() || cgroup_config.is_enabled() || veth.is_some();
+        let sync = if needs_sync {
+            Some(SyncPipe::new()?)
+        } else {
+            None
+        };
+        let sync_fds = sync.as_ref().map(SyncPipe::child_fds);
 
-        create_overlayfs(&mountpoint, &workdir, &command.layers, &writedir);
+        // Errors before exec are reported to the parent through this pipe.
+        // The child end is closed on a successful exec, so reading it returns EOF.
+        let (report_read, report_write) = pipe2(OFlag::O_CLOEXEC)?;
+        let report_read = AutoCloseFd { fd: report_read }.into_file();
+        let report_write = AutoCloseFd { fd: report_write };
+        let report_fd = report_write.fd;
 
-        // A more full-featured implementation might end up setting an anonymous pipe
-        // between the parent and this child; however, we simply print the error and
-        // return with an error code if anything nasty happens.
+        // Errors are reported through the pipe, so a panic is always a bug in
+        // this library. The panic is reported to the parent, which logs it,
+        // and the child exits without printing, as its stdio already belongs
+        // to the program, and taking the stdout lock or allocating could
+        // deadlock in a copy of a multi-threaded process.
         let old_hook = std::panic::take_hook();
-        std::panic::set_hook(Box::new(|panic_info| {
-            let bt = Backtrace::new();
-            println!("BUG: panic in pre-exec environment!");
-            println!("{}", panic_info);
-            println!("\nBacktrace:\n{:?}", bt);
-            std::process::exit(1);
+        REPORT_FD.with(|fd| fd.set(report_fd));
+        std::panic::set_hook(Box::new(|_| {
+            let _ = write(REPORT_FD.with(Cell::get), &ChildError::Panic.encode());
+            unsafe { libc::_exit(1) };
         }));
 
         let path = command.path;
-        let args = command.args;
+        let exec_path = PathBuf::from(OsStr::from_bytes(path.as_bytes()));
+
+        let current_dir = command.current_dir;
+        let child_current_dir = current_dir.as_deref();
+        let ipc_namespace = command.ipc_namespace;
+        let net_namespace = command.network != NetworkPolicy::Host;
+        let host_sysfs = !net_namespace && user_namespace.is_some();
+        let loopback = !matches!(command.network, NetworkPolicy::None | NetworkPolicy::Host);
+        let groups = command.groups;
+        let mut pre_pivot = command.pre_pivot;
+        let mut pre_exec = command.pre_exec;
+        let dev = if command.mount_dev {
+            Some(command.dev_pts)
+        } else {
+            None
+        };
+        let rootfs = RootfsConfig {
+            mounts: command.mounts,
+            dev,
+            masked_paths: command.masked_paths,
+            mount_shm: ipc_namespace || dev.is_some(),
+            host_sysfs,
+            read_only,
+            writable_sys: command.writable_sys,
+            hide_pids: command.hide_pids,
+        };
+        let seccomp = command.seccomp;
+        let no_new_privs = command.no_new_privs;
+        let capabilities = command.capabilities;
+        let use_init = command.use_init;
+        let hostname = command.hostname;
+        let domainname = command.domainname;
 
-        let mut stack = [0; 4096];
+        let mut clone_flags =
+            CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWUTS;
+        if net_namespace {
+            clone_flags |= CloneFlags::CLONE_NEWNET;
+        }
+        if ipc_namespace {
+            clone_flags |= CloneFlags::CLONE_NEWIPC;
+        }
+        if user_namespace.is_some() {
+            clone_flags |= CloneFlags::CLONE_NEWUSER;
+        }
+        if !needs_sync && !use_init {
+            // The parent must configure the child while it is waiting,
+            // so otherwise it cannot be suspended until exec.
+            // The init process never execs, so it cannot be suspended either.
+            clone_flags |= CloneFlags::CLONE_VFORK;
+        }
+
+        // The child gets its own copy of the parent memory, including this
+        // mapping, so it stays valid there until exec even without CLONE_VFORK.
+        // Still held until the end of the function, as clone requires.
+        let mut stack = CloneStack::new(command.clone_stack_size)?;
         let id = clone(
             Box::new(move || {
                 // In post-clone, pre-exec environment.
                 // Many rust features do not work properly here, for instance:
-                // * If the code panics, it causes a segfault after printing the panic message
-
-                // Argument callback
-                // if let Some(f) = pre_pivot.take() {
-                //     f().expect("pre_pivot failed");
-                // }
+                // * If the code panics, the hook above only reports it to the parent and exits
 
                 // Do process setup before exec
-                setup_rootfs(&mountpoint);
+                let setup = (|| {
+                    let hook_error = |err: nix::Error| ChildError::Hook(Error::from(err).errno());
+
+                    if let Some((read_fd, write_fd)) = sync_fds {
+                        wait_for_parent(read_fd, write_fd).step(SetupStep::Sync)?;
+                    }
+                    setup_uts(&hostname, domainname.as_deref()).step(SetupStep::Uts)?;
+                    if loopback {
+                        net::setup_loopback().step(SetupStep::Loopback)?;
+                    }
+                    if let Some((host_addr, container_addr, prefix_len, _)) = veth {
+                        net::setup_veth(container_addr, host_addr, prefix_len)
+                            .step(SetupStep::Veth)?;
+                    }
+                    if !mounted {
+                        mount_layers(
+                            &mountpoint,
+                            &workdir,
+                            &layers,
+                            &writable_layers,
+                            &writedir,
+                            bind_layer,
+                            nosuid,
+                        )
+                        .step(SetupStep::OverlayMount)?;
+                    }
+
+                    for hook in pre_pivot.drain(..) {
+                        hook(()).map_err(hook_error)?;
+                    }
 
-                // Argument callback
-                // if let Some(f) = pre_exec.take() {
-                //     f().expect("pre_exec failed");
-                // }
+                    setup_rootfs(&mountpoint, &rootfs)?;
+                    setup_stdio(stdin_fd, stdout_fd, stderr_fd).step(SetupStep::Stdio)?;
+                    for &(resource, soft, hard) in &rlimits {
+                        rlimit::setrlimit(resource, soft, hard).step(SetupStep::Rlimit)?;
+                    }
+                    // After the mounts, which need CAP_SYS_ADMIN, and before
+                    // changing the user, which loses CAP_SETPCAP
+                    if let Some(keep) = &capabilities {
+                        capability::drop_bounding(keep).step(SetupStep::Capabilities)?;
+                    }
+                    setup_credentials(uid, gid, groups.as_deref(), deny_setgroups)
+                        .step(SetupStep::Credentials)?;
+                    if let Some(keep) = &capabilities {
+                        capability::restrict(keep).step(SetupStep::Capabilities)?;
+                    }
+
+                    if let Some(dir) = child_current_dir {
+                        chdir(dir)
+                            .map_err(|err| ChildError::CurrentDir(Error::from(err).errno()))?;
+                    }
+
+                    for hook in pre_exec.drain(..) {
+                        hook(()).map_err(hook_error)?;
+                    }
+
+                    // This process stays as PID 1 to reap orphans, and the program is forked
+                    if use_init {
+                        if let ForkResult::Parent { child } =
+                            unsafe { fork() }.step(SetupStep::Init)?
+                        {
+                            return Ok(Some(child));
+                        }
+                    }
+
+                    if no_new_privs {
+                        set_no_new_privs().step(SetupStep::NoNewPrivs)?;
+                    }
+                    // Last, so that the filter only applies to the new program
+                    if let Some(policy) = &seccomp {
+                        policy.install().step(SetupStep::Seccomp)?;
+                    }
+                    Ok(None)
+                })();
 
                 // Change into the next process
-                execv(path.as_c_str(), &args).expect("execv failed");
-                unreachable!();
+                let error = match setup {
+                    Ok(Some(program)) => {
+                        // Errors of the program are reported by itself,
+                        // and the report pipe is closed with the other fds
+                        init::release_fds();
+                        return init::run(program);
+                    }
+                    Ok(None) => {
+                        let err = execve(path.as_c_str(), &args, &env).unwrap_err();
+                        ChildError::Exec(Error::from(err).errno())
+                    }
+                    Err(err) => err,
+                };
+
+                // Only reached on failure, nothing can be done if reporting fails
+                let _ = write(report_fd, &error.encode());
+                127
             }),
-            &mut stack,
-            CloneFlags::CLONE_VFORK
-                | CloneFlags::CLONE_NEWNS
-                | CloneFlags::CLONE_NEWPID
-                | CloneFlags::CLONE_NEWNET,
+            stack.as_mut_slice(),
+            clone_flags,
             Some(Signal::SIGCHLD as i32),
-        )
-        .expect("Clone failed");
+        );
 
         // Restore old panic hook
         std::panic::set_hook(old_hook);
+        REPORT_FD.with(|fd| fd.set(-1));
+
+        let id = id.map_err(|err| {
+            let errno = Error::from(err).errno();
+            if user_namespace.is_some() {
+                // Most likely user namespaces are disabled or limited on this system
+                Error::UserNamespace(errno)
+            } else {
+                Error::Clone(errno)
+            }
+        })?;
+        let pidfd = pidfd_open(id).ok();
+        span.record_pid(id);
+        event!(debug, "cloned the child process");
+
+        // Configure the user namespace, the cgroup and the network, and let the child continue
+        if let Some(sync) = sync {
+            let configured = (|| {
+                if let Some(maps) = &user_namespace {
+                    write_id_maps(id, maps, deny_setgroups)
+                        .map_err(|err| Error::UserNamespace(io_errno(&err)))?;
+                }
+                if cgroup_config.is_enabled() {
+                    resources.cgroup = Some(Cgroup::create(&cgroup_config, id)?);
+                }
+                if let Some((host_addr, _, prefix_len, enable_nat)) = veth {
+                    let veth = Veth::create(id, host_addr, prefix_len, enable_nat)
+                        .map_err(|err| Error::Network(Error::from(err).errno()))?;
+                    resources.veth = Some(veth);
+                }
+                Ok(())
+            })();
+            if let Err(err) = configured {
+                sync.abort();
+                waitpid(id, None)?;
+                return Err(err);
+            }
+            sync.release()?;
+        }
+
+        // Check whether the child reached exec successfully
+        drop(report_write);
+        let mut report = Vec::new();
+        (&report_read).read_to_end(&mut report)?;
+        if !report.is_empty() {
+            waitpid(id, None)?;
+            let error = ChildError::decode(&report).ok_or(Error::MalformedReport)?;
+            return Err(match error {
+                ChildError::Setup(step, source) => Error::Setup { step, source },
+                ChildError::Hook(errno) => Error::Hook(errno),
+                ChildError::CurrentDir(source) => Error::CurrentDir {
+                    path: current_dir.unwrap(),
+                    source,
+                },
+                ChildError::Exec(source) => Error::Exec {
+                    path: exec_path,
+                    source,
+                },
+                ChildError::Panic => {
+                    event!(error, "BUG: panic in pre-exec environment");
+                    Error::ChildPanic
+                }
+            });
+        }
+
+        event!(debug, "set up the container and executed the program");
+
+        // Close the child ends of the streams, so that EOF is delivered properly
+        drop(stdin.child.take());
+        drop(stdout.child);
+        drop(stderr.child);
+
+        stdin.feed(&command.stdin);
 
-        Ok(Process {
+        let mut process = Process {
             id,
             status: None,
-            resources: HeldResources { tmp },
-        })
+            resources,
+            must_wait: false,
+            grace_period: command.grace_period,
+            pid_limit_method,
+            time_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            pidfd,
+            span,
+            stdin: stdin.parent.map(|inner| ChildStdin { inner }),
+            stdout: stdout.parent.map(|inner| ChildStdout { inner }),
+            stderr: stderr.parent.map(|inner| ChildStderr { inner }),
+        };
+
+        // On failure, the process is killed when dropped
+        if let Some(limit) = command.time_limit {
+            let pidfd = pidfd_open(id)?;
+            spawn_watchdog(pidfd, limit, process.time_limit_exceeded.clone());
+        }
+
+        process.must_wait = command.must_wait;
+        Ok(process)
+    }
+
+    /// Process id of the child on the host, like `std::process::Child::id`.
+    /// After the process has been waited for, the id may be reused by another process.
+    pub fn id(&self) -> u32 {
+        self.id.as_raw() as u32
+    }
+
+    /// Pidfd of the process, which can be polled for termination or used to
+    /// send signals race-free. `None` on kernels older than 5.3.
+    /// Owned by the process, so it must not be closed.
+    pub fn pidfd(&self) -> Option<RawFd> {
+        self.pidfd.as_ref().map(|pidfd| pidfd.fd)
+    }
+
+    /// Waits for the process using the pidfd if available, like `waitpid`
+    fn waitpid(&self, nohang: bool) -> nix::Result<WaitStatus> {
+        if let Some(pidfd) = &self.pidfd {
+            match waitid_pidfd(pidfd, self.id, nohang) {
+                // P_PIDFD requires Linux 5.4, one version later than pidfd_open
+                Err(nix::Error::Sys(Errno::EINVAL)) => {}
+                other => return other,
+            }
+        }
+        let flags = if nohang {
+            Some(WaitPidFlag::WNOHANG)
+        } else {
+            None
+        };
+        waitpid(self.id, flags)
+    }
+
+    /// Stores the exit status, and releases the network configuration of the
+    /// host, which is not needed after the process has exited
+    fn set_status(&mut self, status: WaitStatus) -> ExitStatus {
+        let status = ExitStatus::new(status);
+        self.span
+            .in_scope(|| event!(debug, status = %status, "process exited"));
+        self.status = Some(status);
+        self.resources.veth = None;
+        status
     }
 
     /// Wait until the process completes, and return it's status.
-    pub fn wait(&mut self) -> nix::Result<WaitStatus> {
+    pub fn wait(&mut self) -> Result<ExitStatus> {
         if let Some(old_status) = self.status {
-            Ok(old_status)
+            return Ok(old_status);
+        }
+
+        loop {
+            // Stops are only reported for traced processes, and are not final
+            let status = self.waitpid(false)?;
+            if status::is_final(status) {
+                return Ok(self.set_status(status));
+            }
+        }
+    }
+
+    /// Like `wait`, but returns the status as reported by `waitpid`.
+    #[deprecated(note = "use `wait` and `ExitStatus::raw` instead")]
+    pub fn raw_wait(&mut self) -> Result<WaitStatus> {
+        self.wait().map(|status| status.raw())
+    }
+
+    /// Waits until the process completes without blocking the current thread,
+    /// for use in async code. The returned future works with any executor.
+    pub fn wait_async(&mut self) -> WaitFuture<'_> {
+        WaitFuture::new(self)
+    }
+
+    /// Check whether the process has completed, without blocking.
+    /// Returns `None` if the process is still running.
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
+        if let Some(old_status) = self.status {
+            return Ok(Some(old_status));
+        }
+
+        loop {
+            match self.waitpid(true)? {
+                WaitStatus::StillAlive => return Ok(None),
+                status if status::is_final(status) => return Ok(Some(self.set_status(status))),
+                // Stopped or continued, check for a pending exit
+                _ => continue,
+            }
+        }
+    }
+
+    /// Wait until the process completes or the timeout expires.
+    /// Returns `None` if the process is still running after the timeout.
+    /// Does not use signals, so `SIGCHLD` handlers are not affected.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<Option<ExitStatus>> {
+        if let Some(status) = self.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let deadline = Instant::now() + timeout;
+        match &self.pidfd {
+            Some(pidfd) => {
+                if poll_pidfd(pidfd, deadline)? {
+                    self.try_wait()
+                } else {
+                    Ok(None)
+                }
+            }
+            None => {
+                // Kernels older than 5.3 do not have pidfd, so poll with backoff instead
+                let mut interval = Duration::from_millis(1);
+                loop {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining == Duration::from_secs(0) {
+                        return Ok(None);
+                    }
+                    std::thread::sleep(interval.min(remaining));
+                    if let Some(status) = self.try_wait()? {
+                        return Ok(Some(status));
+                    }
+                    interval = (interval * 2).min(Duration::from_millis(50));
+                }
+            }
+        }
+    }
+
+    /// Exit code of the process, or `None` if it was terminated by a signal
+    /// or has not been waited for yet
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.and_then(|status| status.code())
+    }
+
+    /// Whether the process has been waited for and exited with code zero
+    pub fn success(&self) -> bool {
+        self.status.is_some_and(|status| status.success())
+    }
+
+    /// Signal that terminated the process, or `None` if it exited normally
+    /// or has not been waited for yet
+    pub fn signaled(&self) -> Option<Signal> {
+        self.status.and_then(|status| status.signal())
+    }
+
+    /// Whether the process was killed because it exceeded `Command::time_limit`.
+    /// Only known after the process has been waited for.
+    pub fn timed_out(&self) -> bool {
+        let signal = self.status.and_then(|status| status.signal());
+        signal == Some(Signal::SIGKILL) && self.time_limit_exceeded.load(Ordering::SeqCst)
+    }
+
+    /// How the limit set with `Command::max_pids` is enforced,
+    /// or `None` if there is no such limit
+    pub fn pid_limit_method(&self) -> Option<PidLimitMethod> {
+        self.pid_limit_method
+    }
+
+    /// Whether a process in the container was killed by the OOM killer,
+    /// because of the limit set with `Command::memory_limit_bytes`.
+    /// Always `false` if no memory limit was set.
+    pub fn oom_killed(&self) -> bool {
+        let cgroup = self.resources.cgroup.as_ref();
+        cgroup.is_some_and(|cgroup| cgroup.oom_kills() > 0)
+    }
+
+    /// Upper directory of the overlay, containing the files the container
+    /// has created or modified. With `DiskWritePolicy::TempDir`, it is deleted
+    /// when the process is dropped, unless `into_writedir` is used.
+    pub fn writedir(&self) -> &Path {
+        &self.resources.writedir
+    }
+
+    /// Merged root filesystem of the container, as seen from the host.
+    /// Only valid while the filesystem is mounted, which is until the process
+    /// is dropped. `None` when the filesystem is mounted in the user namespace
+    /// of the container instead, and is not visible here.
+    pub fn mountpoint(&self) -> Option<&Path> {
+        if self.resources.mounted {
+            Some(&self.resources.mountpoint)
         } else {
-            let status = waitpid(self.id, None)?;
-            self.status = Some(status);
-            Ok(status)
+            None
         }
     }
 
-    /// Send a signal to the process.
-    /// Panics if `wait` has returned succesfully before.
-    pub fn signal(&mut self, signal: Signal) -> nix::Result<()> {
+    /// Kills the process if it is still running, unmounts the root filesystem,
+    /// and returns the path of `writedir`, which will no longer be deleted.
+    /// The caller becomes responsible for removing it and its parent directory.
+    pub fn into_writedir(mut self) -> Result<PathBuf> {
+        self.kill()?;
+        self.resources.unmount().map_err(|err| match err {
+            nix::Error::Sys(source) => Error::Mount {
+                path: self.resources.mountpoint.clone(),
+                source,
+            },
+            other => other.into(),
+        })?;
+        let tmp = self.resources.tmp.take();
+        if let Some(tmp) = tmp.filter(|tmp| self.resources.writedir.starts_with(tmp.path())) {
+            // Only the write directory is of interest
+            let tmp = tmp.keep();
+            let _ = std::fs::remove_dir(tmp.join("mount"));
+            let _ = std::fs::remove_dir_all(tmp.join("work"));
+            for stage in &self.resources.stages {
+                let _ = std::fs::remove_dir_all(stage.parent().unwrap());
+            }
+        }
+        Ok(self.resources.writedir.clone())
+    }
+
+    /// Closes stdin, reads all remaining data from stdout and stderr,
+    /// and then waits for the process to complete.
+    /// Similar to `std::process::Child::wait_with_output`.
+    pub fn output(mut self) -> Result<Output> {
+        drop(self.stdin.take());
+
+        // Drain stderr in a separate thread when both are piped, so that
+        // the process cannot block on a full pipe we are not reading
+        let stderr_pipe = self.stderr.take();
+        let stderr_reader = move || -> std::io::Result<Vec<u8>> {
+            let mut stderr = Vec::new();
+            if let Some(mut pipe) = stderr_pipe {
+                pipe.read_to_end(&mut stderr)?;
+            }
+            Ok(stderr)
+        };
+
+        let (stdout, stderr) = if let Some(mut pipe) = self.stdout.take() {
+            let stderr_thread = std::thread::spawn(stderr_reader);
+            let mut stdout = Vec::new();
+            let stdout_result = pipe.read_to_end(&mut stdout);
+            let stderr = stderr_thread
+                .join()
+                .expect("stderr reader thread panicked")?;
+            stdout_result?;
+            (stdout, stderr)
+        } else {
+            (Vec::new(), stderr_reader()?)
+        };
+
+        let status = self.wait()?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Send a signal to the process, using the pidfd if available.
+    /// Returns `ESRCH` if `wait` has returned succesfully before,
+    /// as the process id may have been reused.
+    pub fn signal(&mut self, signal: Signal) -> Result<()> {
         use nix::sys::signal::kill;
 
         if self.status.is_some() {
-            panic!("Attempting to send a signal to a known-dead process");
+            return Err(Error::Sys(Errno::ESRCH));
+        }
+
+        match &self.pidfd {
+            Some(pidfd) => Ok(pidfd_send_signal(pidfd, signal)?),
+            None => Ok(kill(self.id, signal)?),
+        }
+    }
+
+    /// Forcibly terminates the process with `SIGKILL`, and waits for it.
+    /// Does nothing if the process has already been waited for.
+    pub fn kill(&mut self) -> Result<()> {
+        if self.status.is_none() {
+            self.signal(Signal::SIGKILL)?;
+            self.wait()?;
         }
+        Ok(())
+    }
 
-        kill(self.id, signal)
+    /// Asks the process to terminate with `SIGTERM`, and if it is still
+    /// running after the timeout, kills it with `SIGKILL`.
+    /// Returns the status of the terminated process.
+    pub fn terminate(&mut self, timeout: Duration) -> Result<ExitStatus> {
+        self.signal(Signal::SIGTERM)?;
+        if let Some(status) = self.wait_timeout(timeout)? {
+            return Ok(status);
+        }
+        self.kill()?;
+        self.wait()
+    }
+
+    /// Like `terminate`, using the grace period set with `Command::grace_period`,
+    /// or 10 seconds by default. Returns the status if already waited for.
+    pub fn shutdown(&mut self) -> Result<ExitStatus> {
+        if let Some(status) = self.status {
+            return Ok(status);
+        }
+        self.terminate(self.grace_period.unwrap_or(DEFAULT_GRACE_PERIOD))
     }
 }
 
 impl Drop for Process {
     fn drop(&mut self) {
-        if self.status.is_none() {
-            panic!("Dropping a running process");
-            // self.inner.cleanup();
+        let running = self.status.is_none();
+        self.span
+            .in_scope(|| event!(debug, running, "dropping the process"));
+        if running {
+            if self.must_wait && !std::thread::panicking() {
+                panic!("Dropping a running process");
+            }
+            let result = match self.grace_period {
+                Some(_) => self.shutdown().map(drop),
+                None => self.kill(),
+            };
+            // Nothing else can be done here, so the error is only logged
+            #[cfg(feature = "tracing")]
+            if let Err(err) = &result {
+                self.span.in_scope(
+                    || tracing::warn!(error = %err, "could not kill the dropped process"),
+                );
+            }
+            #[cfg(not(feature = "tracing"))]
+            let _ = result;
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+    use super::overlayfs_escape_path;
+
+    fn escape(path: &str) -> Vec<u8> {
+        overlayfs_escape_path(Path::new(path))
+    }
+
+    #[test]
+    fn escape_empty() {
+        assert_eq!(escape(""), b"");
+    }
+
+    #[test]
+    fn escape_plain() {
+        assert_eq!(escape("/var/lib/layer"), b"/var/lib/layer");
+    }
+
+    #[test]
+    fn escape_separators() {
+        assert_eq!(escape("a\\b"), b"a\\\\b");
+        assert_eq!(escape("a:b"), b"a\\:b");
+        assert_eq!(escape("a,b"), b"a\\,b");
+        assert_eq!(escape("\\:,"), b"\\\\\\:\\,");
+    }
+
+    #[test]
+    fn escape_already_escaped() {
+        // Escaping is not idempotent, every backslash is taken literally
+        assert_eq!(escape("a\\:b"), b"a\\\\\\:b");
+    }
+
+    #[test]
+    fn escape_unicode() {
+        assert_eq!(escape("/tmp/päivä:ö"), "/tmp/päivä\\:ö".as_bytes());
+        let raw = OsStr::from_bytes(b"/tmp/\xff,");
+        assert_eq!(overlayfs_escape_path(Path::new(raw)), b"/tmp/\xff\\,");
+    }
+}
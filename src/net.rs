@@ -0,0 +1,315 @@
+use std::net::Ipv4Addr;
+use std::os::unix::io::AsRawFd;
+
+use nix::errno::Errno;
+use nix::net::if_::if_nametoindex;
+use nix::sys::socket::{recv, send, socket, AddressFamily, MsgFlags, SockFlag, SockType};
+use nix::unistd::Pid;
+
+use crate::error::io_errno;
+use crate::AutoCloseFd;
+
+/// Network access of the container, see `Command::network`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkPolicy {
+    /// A new network namespace, where only the loopback interface is up
+    Isolated,
+    /// A new network namespace without any usable interfaces
+    None,
+    /// The network namespace of the host. This reduces isolation: the process
+    /// can reach any network the host can, and bind to ports of the host.
+    Host,
+    /// A new network namespace connected to the host with a veth pair.
+    /// The interface `eth0` of the container has `container_addr`, and the
+    /// interface of the host has `host_addr`, both in a `prefix_len` subnet.
+    /// The default route of the container points at the host end.
+    /// Configuring the host side requires root privileges on the host.
+    ///
+    /// With `enable_nat`, a masquerade rule for the subnet is added with
+    /// `iptables`, so that the container can reach other networks through
+    /// the host. IP forwarding must be enabled on the host separately.
+    Veth {
+        host_addr: Ipv4Addr,
+        container_addr: Ipv4Addr,
+        prefix_len: u8,
+        enable_nat: bool,
+    },
+}
+
+// Constants from <linux/netlink.h> and <linux/rtnetlink.h>, not provided by nix
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_NEWADDR: u16 = 20;
+const RTM_NEWROUTE: u16 = 24;
+const IFLA_IFNAME: u16 = 3;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_NET_NS_FD: u16 = 28;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_DATA: u16 = 2;
+const VETH_INFO_PEER: u16 = 1;
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+const RTA_GATEWAY: u16 = 5;
+const RT_TABLE_MAIN: u8 = 254;
+const RTPROT_BOOT: u8 = 3;
+const RT_SCOPE_UNIVERSE: u8 = 0;
+const RTN_UNICAST: u8 = 1;
+const IFF_UP: u32 = 0x1;
+const AF_INET: u8 = 2;
+
+/// Name of the interface inside the container with `NetworkPolicy::Veth`
+const CONTAINER_VETH: &str = "eth0";
+
+/// Size of `struct nlmsghdr`
+const HEADER_LEN: usize = 16;
+
+/// Attributes and messages are padded to four bytes
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// An rtnetlink request message, built in the kernel wire format
+struct Request {
+    buf: Vec<u8>,
+}
+
+impl Request {
+    fn new(kind: u16, flags: u16) -> Self {
+        let mut buf = vec![0; HEADER_LEN];
+        buf[4..6].copy_from_slice(&kind.to_ne_bytes());
+        buf[6..8].copy_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        Self { buf }
+    }
+
+    /// Appends a `struct ifinfomsg`
+    fn ifinfo(mut self, index: i32, flags: u32, change: u32) -> Self {
+        // Family, padding and device type are left unspecified
+        self.buf.extend_from_slice(&[0; 4]);
+        self.buf.extend_from_slice(&index.to_ne_bytes());
+        self.buf.extend_from_slice(&flags.to_ne_bytes());
+        self.buf.extend_from_slice(&change.to_ne_bytes());
+        self
+    }
+
+    /// Appends a `struct ifaddrmsg` for an IPv4 address
+    fn ifaddr(mut self, prefix_len: u8, index: u32) -> Self {
+        // Flags and scope are left unspecified
+        self.buf.extend_from_slice(&[AF_INET, prefix_len, 0, 0]);
+        self.buf.extend_from_slice(&index.to_ne_bytes());
+        self
+    }
+
+    /// Appends a `struct rtmsg` for an IPv4 route in the main table
+    fn rtmsg(mut self, dst_len: u8) -> Self {
+        // Source length, TOS and flags are left unspecified
+        self.buf.extend_from_slice(&[AF_INET, dst_len, 0, 0]);
+        self.buf
+            .extend_from_slice(&[RT_TABLE_MAIN, RTPROT_BOOT, RT_SCOPE_UNIVERSE, RTN_UNICAST]);
+        self.buf.extend_from_slice(&[0; 4]);
+        self
+    }
+
+    /// Appends a `struct rtattr` with its data
+    fn attr(mut self, kind: u16, data: &[u8]) -> Self {
+        let len = 4 + data.len();
+        self.buf.extend_from_slice(&(len as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        self.buf.resize(align(self.buf.len()), 0);
+        self
+    }
+
+    /// Appends a `struct rtattr` containing whatever `build` appends
+    fn nested(mut self, kind: u16, build: impl FnOnce(Self) -> Self) -> Self {
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&[0; 2]);
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        let mut request = build(self);
+        let len = (request.buf.len() - start) as u16;
+        request.buf[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        request
+    }
+
+    /// Appends a nul-terminated interface name attribute
+    fn ifname(self, name: &str) -> Self {
+        let mut data = name.as_bytes().to_vec();
+        data.push(0);
+        self.attr(IFLA_IFNAME, &data)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let len = self.buf.len() as u32;
+        self.buf[..4].copy_from_slice(&len.to_ne_bytes());
+        self.buf
+    }
+}
+
+/// A route netlink socket of the current network namespace
+struct Netlink {
+    socket: AutoCloseFd,
+}
+
+impl Netlink {
+    fn open() -> nix::Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        Ok(Self {
+            socket: AutoCloseFd { fd },
+        })
+    }
+
+    /// Sends a request, and waits for the kernel to acknowledge it
+    fn request(&self, request: Request) -> nix::Result<()> {
+        send(self.socket.fd, &request.finish(), MsgFlags::empty())?;
+
+        let mut reply = [0; 1024];
+        let len = recv(self.socket.fd, &mut reply, MsgFlags::empty())?;
+        let kind = reply.get(4..6).map(|b| u16::from_ne_bytes([b[0], b[1]]));
+        if len < HEADER_LEN + 4 || kind != Some(NLMSG_ERROR) {
+            return Err(nix::Error::Sys(Errno::EBADMSG));
+        }
+
+        // The acknowledgement is an error message with a zero code
+        let mut code = [0; 4];
+        code.copy_from_slice(&reply[HEADER_LEN..HEADER_LEN + 4]);
+        match i32::from_ne_bytes(code) {
+            0 => Ok(()),
+            code => Err(nix::Error::Sys(Errno::from_i32(-code))),
+        }
+    }
+
+    /// Assigns an IPv4 address to an interface, and brings it up
+    fn configure(&self, name: &str, addr: Ipv4Addr, prefix_len: u8) -> nix::Result<()> {
+        let index = if_nametoindex(name)?;
+        self.request(
+            Request::new(RTM_NEWADDR, NLM_F_CREATE | NLM_F_EXCL)
+                .ifaddr(prefix_len, index)
+                .attr(IFA_LOCAL, &addr.octets())
+                .attr(IFA_ADDRESS, &addr.octets()),
+        )?;
+        self.request(Request::new(RTM_NEWLINK, 0).ifinfo(index as i32, IFF_UP, IFF_UP))
+    }
+}
+
+/// Brings up the loopback interface, which is down in a new network namespace
+pub(crate) fn setup_loopback() -> nix::Result<()> {
+    let netlink = Netlink::open()?;
+    netlink.request(
+        Request::new(RTM_NEWLINK, 0)
+            .ifinfo(0, IFF_UP, IFF_UP)
+            .ifname("lo"),
+    )
+}
+
+/// Configures the container end of the veth pair created by `Veth::create`,
+/// with the default route through the host end
+pub(crate) fn setup_veth(addr: Ipv4Addr, gateway: Ipv4Addr, prefix_len: u8) -> nix::Result<()> {
+    let netlink = Netlink::open()?;
+    netlink.configure(CONTAINER_VETH, addr, prefix_len)?;
+    netlink.request(
+        Request::new(RTM_NEWROUTE, NLM_F_CREATE | NLM_F_EXCL)
+            .rtmsg(0)
+            .attr(RTA_GATEWAY, &gateway.octets()),
+    )
+}
+
+/// Adds or deletes the masquerade rule of a veth pair, using `iptables` of the host
+fn masquerade(action: &str, subnet: &str, name: &str) -> nix::Result<()> {
+    // Traffic between the host and the container is not translated
+    let status = std::process::Command::new("iptables")
+        .args(["-w", "-t", "nat", action, "POSTROUTING", "-s", subnet])
+        .args(["!", "-o", name, "-j", "MASQUERADE"])
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .status()
+        .map_err(|err| nix::Error::Sys(io_errno(&err)))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(nix::Error::Sys(Errno::EIO))
+    }
+}
+
+/// The host end of a veth pair, deleted on drop with its masquerade rule.
+/// The container end is deleted with the network namespace anyway.
+pub(crate) struct Veth {
+    name: String,
+    /// Source subnet of the masquerade rule, if one was added
+    nat_subnet: Option<String>,
+}
+
+impl Veth {
+    /// Creates a veth pair with the other end in the network namespace of
+    /// the process, and configures the host end
+    pub(crate) fn create(
+        pid: Pid,
+        host_addr: Ipv4Addr,
+        prefix_len: u8,
+        enable_nat: bool,
+    ) -> nix::Result<Self> {
+        let netns = std::fs::File::open(format!("/proc/{}/ns/net", pid))
+            .map_err(|err| nix::Error::Sys(io_errno(&err)))?;
+        let netns_fd = netns.as_raw_fd() as u32;
+
+        // Fits in IFNAMSIZ, as pids have at most seven digits
+        let name = format!("isolated{}", pid);
+        let netlink = Netlink::open()?;
+        netlink.request(
+            Request::new(RTM_NEWLINK, NLM_F_CREATE | NLM_F_EXCL)
+                .ifinfo(0, 0, 0)
+                .ifname(&name)
+                .nested(IFLA_LINKINFO, |info| {
+                    info.attr(IFLA_INFO_KIND, b"veth")
+                        .nested(IFLA_INFO_DATA, |data| {
+                            data.nested(VETH_INFO_PEER, |peer| {
+                                peer.ifinfo(0, 0, 0)
+                                    .ifname(CONTAINER_VETH)
+                                    .attr(IFLA_NET_NS_FD, &netns_fd.to_ne_bytes())
+                            })
+                        })
+                }),
+        )?;
+
+        // Deleted again if configuring fails
+        let mut veth = Self {
+            name,
+            nat_subnet: None,
+        };
+        netlink.configure(&veth.name, host_addr, prefix_len)?;
+        if enable_nat {
+            let mask = u32::MAX
+                .checked_shl(32_u32.saturating_sub(prefix_len.into()))
+                .unwrap_or(0);
+            let network = Ipv4Addr::from(u32::from(host_addr) & mask);
+            let subnet = format!("{}/{}", network, prefix_len);
+            masquerade("-A", &subnet, &veth.name)?;
+            veth.nat_subnet = Some(subnet);
+        }
+        Ok(veth)
+    }
+}
+
+impl Drop for Veth {
+    fn drop(&mut self) {
+        // Errors are ignored, as the pair is gone if the namespace was destroyed
+        if let Some(subnet) = &self.nat_subnet {
+            let _ = masquerade("-D", subnet, &self.name);
+        }
+        if let Ok(netlink) = Netlink::open() {
+            let _ = netlink.request(
+                Request::new(RTM_DELLINK, 0)
+                    .ifinfo(0, 0, 0)
+                    .ifname(&self.name),
+            );
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use nix::errno::Errno;
+
+/// Value for an unlimited resource
+pub const RLIM_INFINITY: u64 = libc::RLIM_INFINITY;
+
+/// Resources that can be limited with `Command::rlimit`, see `setrlimit(2)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// Maximum size of the virtual memory in bytes (`RLIMIT_AS`)
+    AddressSpace,
+    /// Maximum size of core dumps in bytes (`RLIMIT_CORE`)
+    CoreSize,
+    /// Maximum CPU time in seconds (`RLIMIT_CPU`)
+    CpuTime,
+    /// Maximum size of the data segment in bytes (`RLIMIT_DATA`)
+    DataSize,
+    /// Maximum size of created files in bytes (`RLIMIT_FSIZE`)
+    FileSize,
+    /// Maximum amount of locked memory in bytes (`RLIMIT_MEMLOCK`)
+    LockedMemory,
+    /// Maximum number of open file descriptors (`RLIMIT_NOFILE`)
+    OpenFiles,
+    /// Maximum number of processes of the user (`RLIMIT_NPROC`)
+    Processes,
+    /// Maximum size of the stack in bytes (`RLIMIT_STACK`)
+    StackSize,
+}
+
+/// Sets a resource limit of the current process, not provided by nix
+pub(crate) fn setrlimit(resource: Resource, soft: u64, hard: u64) -> nix::Result<()> {
+    // The type of the constants depends on the libc implementation
+    let resource = match resource {
+        Resource::AddressSpace => libc::RLIMIT_AS,
+        Resource::CoreSize => libc::RLIMIT_CORE,
+        Resource::CpuTime => libc::RLIMIT_CPU,
+        Resource::DataSize => libc::RLIMIT_DATA,
+        Resource::FileSize => libc::RLIMIT_FSIZE,
+        Resource::LockedMemory => libc::RLIMIT_MEMLOCK,
+        Resource::OpenFiles => libc::RLIMIT_NOFILE,
+        Resource::Processes => libc::RLIMIT_NPROC,
+        Resource::StackSize => libc::RLIMIT_STACK,
+    };
+    let limit = libc::rlimit {
+        rlim_cur: soft,
+        rlim_max: hard,
+    };
+    let res = unsafe { libc::setrlimit(resource, &limit) };
+    Errno::result(res).map(drop)
+}
@@ -0,0 +1,178 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use nix::unistd::Uid;
+
+use crate::command::DiskWritePolicy;
+use crate::{Command, PreparedRoot, Process, Result};
+
+/// Number of root filesystems mounted ahead of time unless configured
+const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Root filesystems ready to be handed out, shared with the refill thread
+type Pool = Arc<Mutex<Vec<PreparedRoot>>>;
+
+/// Held while spawning, and by the refill thread while mounting
+type Busy = Arc<Mutex<()>>;
+
+/// Configures a `Sandbox`.
+#[must_use]
+pub struct SandboxBuilder {
+    layers: Vec<PathBuf>,
+    pool_size: usize,
+}
+
+impl SandboxBuilder {
+    /// Read-only root filesystem of the processes, like in `Command::new`.
+    pub fn new<P: AsRef<Path>>(root_fs: P) -> Self {
+        Self {
+            layers: vec![root_fs.as_ref().to_owned()],
+            pool_size: DEFAULT_POOL_SIZE,
+        }
+    }
+
+    /// Adds a read-only layer on top of the previous ones, like `Command::layer`.
+    pub fn layer<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.layers.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Number of root filesystems kept mounted ahead of time.
+    /// Zero disables the pool, so every process mounts its own.
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.pool_size = size;
+        self
+    }
+
+    /// Mounts the initial pool, so that an invalid layer is reported here.
+    pub fn build(self) -> Result<Sandbox> {
+        let mut pool = Vec::new();
+        if Uid::effective().is_root() {
+            for _ in 0..self.pool_size {
+                pool.push(prepare(self.layers.clone())?);
+            }
+        }
+        Ok(Sandbox {
+            layers: self.layers,
+            pool_size: self.pool_size,
+            pool: Arc::new(Mutex::new(pool)),
+            busy: Arc::new(Mutex::new(())),
+            refill: None,
+        })
+    }
+}
+
+/// Spawns many processes on the same layers, with their overlays mounted
+/// ahead of time, so that spawning does not wait for the mount.
+/// The pool is refilled on a background thread after each spawn.
+///
+/// Only the root filesystem is prepared, as the namespaces are created by
+/// `clone` for each process. The pool is used when running as root without
+/// a user namespace, since otherwise the child mounts its own overlay,
+/// and only for commands on the same layers that keep the default write
+/// policy and `nosuid`, and have no writable layers. Other commands are
+/// spawned as usual.
+///
+/// The child of `clone` is a copy of the parent, and must not inherit a lock
+/// held by another thread, like the allocator's. So the refill thread only
+/// mounts between spawns, and waits without holding any lock during a spawn.
+pub struct Sandbox {
+    layers: Vec<PathBuf>,
+    pool_size: usize,
+    pool: Pool,
+    busy: Busy,
+    /// Thread mounting new root filesystems, joined on drop
+    refill: Option<JoinHandle<()>>,
+}
+
+impl Sandbox {
+    /// Creates a command using the layers of the sandbox.
+    pub fn command(&self, path: &str) -> Command {
+        self.layers[1..]
+            .iter()
+            .fold(Command::new(&self.layers[0], path), Command::layer)
+    }
+
+    /// Number of root filesystems currently mounted ahead of time.
+    pub fn available(&self) -> usize {
+        lock(&self.pool).len()
+    }
+
+    /// Spawns the command, using a root filesystem from the pool if possible.
+    /// Commands that cannot use a pooled root leave the pool untouched.
+    pub fn spawn(&mut self, command: Command) -> Result<Process> {
+        let result = {
+            let _busy = lock(&self.busy);
+            let prepared = {
+                let mut pool = lock(&self.pool);
+                match pool.last() {
+                    Some(root) if root.matches(&command) => pool.pop(),
+                    _ => None,
+                }
+            };
+            Process::spawn_in(command, prepared)
+        };
+        self.refill();
+        result
+    }
+
+    /// Starts mounting new root filesystems, unless already doing so
+    fn refill(&mut self) {
+        if !Uid::effective().is_root() {
+            return;
+        }
+        if self
+            .refill
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+        {
+            return;
+        }
+        if let Some(thread) = self.refill.take() {
+            let _ = thread.join();
+        }
+
+        let layers = self.layers.clone();
+        let pool = Arc::clone(&self.pool);
+        let busy = Arc::clone(&self.busy);
+        let size = self.pool_size;
+        self.refill = Some(std::thread::spawn(move || loop {
+            let _busy = lock(&busy);
+            if lock(&pool).len() >= size {
+                break;
+            }
+            // Errors are reported by the spawns that fall back to mounting
+            match prepare(layers.clone()) {
+                Ok(root) => lock(&pool).push(root),
+                Err(_) => break,
+            }
+        }));
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        // The remaining roots are unmounted when the pool is dropped
+        if let Some(thread) = self.refill.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Mounts a root filesystem for a command with the default write policy and `nosuid`
+fn prepare(layers: Vec<PathBuf>) -> Result<PreparedRoot> {
+    PreparedRoot::new(
+        layers,
+        DiskWritePolicy::TempDir,
+        Vec::new(),
+        false,
+        true,
+        true,
+    )
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Roots are only pushed and popped, so a poisoned pool is still consistent
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
@@ -0,0 +1,193 @@
+use nix::errno::Errno;
+
+/// A system call number, such as `libc::SYS_ptrace`
+pub type Syscall = libc::c_long;
+
+/// Value of `seccomp_data.arch` for the architecture of this build, see `<linux/audit.h>`
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "x86")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0003);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(target_arch = "arm")]
+const AUDIT_ARCH: Option<u32> = Some(0x4000_0028);
+#[cfg(target_arch = "riscv64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00f3);
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "x86",
+    target_arch = "aarch64",
+    target_arch = "arm",
+    target_arch = "riscv64"
+)))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// System calls of the x32 ABI have this bit set, and share the x86_64 arch value
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Offsets of the fields of `seccomp_data`
+const NR_OFFSET: u32 = 0;
+const ARCH_OFFSET: u32 = 4;
+
+/// System calls denied by `SeccompPolicy::deny_dangerous`, which change the
+/// host kernel or escape the namespaces of the container
+const DANGEROUS_SYSCALLS: &[Syscall] = &[
+    libc::SYS_acct,
+    libc::SYS_add_key,
+    libc::SYS_bpf,
+    libc::SYS_clock_settime,
+    libc::SYS_delete_module,
+    libc::SYS_finit_module,
+    libc::SYS_init_module,
+    libc::SYS_kexec_load,
+    libc::SYS_keyctl,
+    libc::SYS_mount,
+    libc::SYS_open_by_handle_at,
+    libc::SYS_perf_event_open,
+    libc::SYS_pivot_root,
+    libc::SYS_ptrace,
+    libc::SYS_reboot,
+    libc::SYS_request_key,
+    libc::SYS_setns,
+    libc::SYS_settimeofday,
+    libc::SYS_swapoff,
+    libc::SYS_swapon,
+    libc::SYS_umount2,
+    libc::SYS_unshare,
+    libc::SYS_userfaultfd,
+];
+
+/// What happens when the process makes a system call, see `seccomp(2)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    /// The system call is executed normally
+    Allow,
+    /// The system call is not executed, and fails with the given error
+    Errno(Errno),
+    /// The whole process is killed with `SIGSYS`. This also applies to the
+    /// spawned process, even though it is the init process of its PID namespace.
+    KillProcess,
+}
+
+impl SeccompAction {
+    fn to_ret(self) -> u32 {
+        match self {
+            Self::Allow => libc::SECCOMP_RET_ALLOW,
+            Self::Errno(errno) => libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA),
+            Self::KillProcess => libc::SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+}
+
+/// A system call filter installed in the process just before exec.
+/// System calls of other architectures, like 32-bit calls on a 64-bit host,
+/// are always killed, as their numbers would not match the rules.
+#[derive(Debug, Clone)]
+pub struct SeccompPolicy {
+    /// Action for system calls without a rule
+    default: SeccompAction,
+    /// Checked in order, the first matching rule applies
+    rules: Vec<(Syscall, SeccompAction)>,
+}
+
+impl SeccompPolicy {
+    /// Creates a policy with no rules, applying `default` to every system call
+    pub fn new(default: SeccompAction) -> Self {
+        Self {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Applies `action` to the system call. If there are multiple rules
+    /// for the same system call, the first one is used.
+    pub fn rule(mut self, syscall: Syscall, action: SeccompAction) -> Self {
+        self.rules.push((syscall, action));
+        self
+    }
+
+    /// Allows only the given system calls, others fail with `EPERM`.
+    /// `execve` is always allowed, as the filter is installed before exec.
+    pub fn default_deny_with_allowlist(syscalls: &[Syscall]) -> Self {
+        std::iter::once(&libc::SYS_execve).chain(syscalls).fold(
+            Self::new(SeccompAction::Errno(Errno::EPERM)),
+            |policy, &nr| policy.rule(nr, SeccompAction::Allow),
+        )
+    }
+
+    /// Allows everything except system calls that modify the host kernel,
+    /// like `mount`, `ptrace` and `kexec_load`, which fail with `EPERM`.
+    /// A baseline for running untrusted programs, which can be extended with `rule`.
+    pub fn deny_dangerous() -> Self {
+        DANGEROUS_SYSCALLS
+            .iter()
+            .fold(Self::new(SeccompAction::Allow), |policy, &nr| {
+                policy.rule(nr, SeccompAction::Errno(Errno::EPERM))
+            })
+    }
+
+    /// Compiles the policy to a classic BPF program
+    fn program(&self, arch: u32) -> Vec<libc::sock_filter> {
+        fn stmt(code: u32, k: u32) -> libc::sock_filter {
+            libc::sock_filter {
+                code: code as u16,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+        fn jump_eq(k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+            libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+                jt,
+                jf,
+                k,
+            }
+        }
+        let load = libc::BPF_LD | libc::BPF_W | libc::BPF_ABS;
+        let ret = libc::BPF_RET | libc::BPF_K;
+        let kill = stmt(ret, libc::SECCOMP_RET_KILL_PROCESS);
+
+        let mut program = vec![stmt(load, ARCH_OFFSET), jump_eq(arch, 1, 0), kill];
+        program.push(stmt(load, NR_OFFSET));
+        if cfg!(target_arch = "x86_64") {
+            // Kill if the x32 bit is set, i.e. if the number is not below it
+            program.push(libc::sock_filter {
+                code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+                jt: 0,
+                jf: 1,
+                k: X32_SYSCALL_BIT,
+            });
+            program.push(kill);
+        }
+        for &(nr, action) in &self.rules {
+            program.push(jump_eq(nr as u32, 0, 1));
+            program.push(stmt(ret, action.to_ret()));
+        }
+        program.push(stmt(ret, self.default.to_ret()));
+        program
+    }
+
+    /// Installs the filter in the current process, not provided by nix.
+    /// Also sets `no_new_privs`, which is required without `CAP_SYS_ADMIN`.
+    pub(crate) fn install(&self) -> nix::Result<()> {
+        let arch = AUDIT_ARCH.ok_or(nix::Error::Sys(Errno::EOPNOTSUPP))?;
+        let program = self.program(arch);
+        let prog = libc::sock_fprog {
+            len: program.len() as u16,
+            filter: program.as_ptr() as *mut _,
+        };
+
+        let res = unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) };
+        Errno::result(res)?;
+        let res = unsafe {
+            libc::prctl(
+                libc::PR_SET_SECCOMP,
+                libc::SECCOMP_MODE_FILTER,
+                &prog as *const libc::sock_fprog,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+
+/// Exit status of a terminated process, similar to `std::process::ExitStatus`.
+/// The underlying `WaitStatus` is available with `raw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitStatus(WaitStatus);
+
+impl ExitStatus {
+    /// Only for statuses of terminated processes
+    pub(crate) fn new(status: WaitStatus) -> Self {
+        debug_assert!(is_final(status));
+        Self(status)
+    }
+
+    /// Whether the process exited with code zero
+    pub fn success(&self) -> bool {
+        self.code() == Some(0)
+    }
+
+    /// Exit code of the process, or `None` if it was terminated by a signal
+    pub fn code(&self) -> Option<i32> {
+        match self.0 {
+            WaitStatus::Exited(_, code) => Some(code),
+            _ => None,
+        }
+    }
+
+    /// Signal that terminated the process, or `None` if it exited normally
+    pub fn signal(&self) -> Option<Signal> {
+        match self.0 {
+            WaitStatus::Signaled(_, signal, _) => Some(signal),
+            _ => None,
+        }
+    }
+
+    /// Whether the process was terminated by a signal and dumped core
+    pub fn core_dumped(&self) -> bool {
+        matches!(self.0, WaitStatus::Signaled(_, _, true))
+    }
+
+    /// Status as returned by `waitpid`, always `Exited` or `Signaled`
+    pub fn raw(&self) -> WaitStatus {
+        self.0
+    }
+}
+
+impl From<ExitStatus> for WaitStatus {
+    fn from(status: ExitStatus) -> Self {
+        status.0
+    }
+}
+
+impl fmt::Display for ExitStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            WaitStatus::Exited(_, code) => write!(f, "exit code: {}", code),
+            WaitStatus::Signaled(_, signal, true) => {
+                write!(f, "signal: {} (core dumped)", signal)
+            }
+            WaitStatus::Signaled(_, signal, false) => write!(f, "signal: {}", signal),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Whether the status means that the process has terminated,
+/// instead of being stopped or continued
+pub(crate) fn is_final(status: WaitStatus) -> bool {
+    matches!(status, WaitStatus::Exited(..) | WaitStatus::Signaled(..))
+}
@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, open, FcntlArg, OFlag};
+use nix::sys::stat::Mode;
+use nix::unistd::pipe2;
+
+use crate::AutoCloseFd;
+
+/// Describes what to do with a standard I/O stream of the isolated process.
+/// Offers an API similar to `std::process::Stdio`.
+#[derive(Debug, Clone)]
+pub enum Stdio {
+    /// The stream is inherited from the parent process
+    Inherit,
+    /// A new pipe is created, and the parent end is made available on `Process`
+    Piped,
+    /// The stream is connected to `/dev/null` of the host
+    Null,
+    /// The stream is connected to a file on the host.
+    /// Output files are created or truncated.
+    File(PathBuf),
+    /// The stream is connected to an existing file descriptor of the parent.
+    /// The descriptor is duplicated, so the caller retains ownership of it.
+    From(RawFd),
+    /// The data is written to the stream from a background thread,
+    /// after which the stream is closed. Only valid for stdin.
+    Bytes(Vec<u8>),
+}
+
+/// File descriptors for a single standard stream, created before clone
+pub(crate) struct StdioPair {
+    /// Installed over the standard stream in the child.
+    /// Closed in the parent when dropped after clone.
+    pub(crate) child: Option<AutoCloseFd>,
+    /// Kept by the parent, exposed through `ChildStdin`, `ChildStdout` or `ChildStderr`
+    pub(crate) parent: Option<File>,
+}
+
+impl StdioPair {
+    /// Starts writing the data of `Stdio::Bytes` to the child.
+    /// The parent end is consumed, and closed after writing.
+    pub(crate) fn feed(&mut self, cfg: &Stdio) {
+        if let Stdio::Bytes(data) = cfg {
+            let mut pipe = self.parent.take().expect("Bytes creates a pipe");
+            let data = data.clone();
+            std::thread::spawn(move || {
+                // The process may exit without reading everything, causing EPIPE
+                let _ = pipe.write_all(&data);
+            });
+        }
+    }
+}
+
+impl Stdio {
+    /// Creates the file descriptors for a stream. The `child_reads` argument
+    /// tells whether the child process reads or writes the stream.
+    pub(crate) fn setup(&self, child_reads: bool) -> nix::Result<StdioPair> {
+        match self {
+            Self::Inherit => Ok(StdioPair {
+                child: None,
+                parent: None,
+            }),
+            Self::Bytes(_) if !child_reads => Err(nix::Error::Sys(Errno::EINVAL)),
+            Self::Piped | Self::Bytes(_) => {
+                // Both ends are closed on exec, dup2 in the child clears the flag
+                let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+                let (read, write) = (AutoCloseFd { fd: read }, AutoCloseFd { fd: write });
+                let (child, parent) = if child_reads {
+                    (read, write)
+                } else {
+                    (write, read)
+                };
+                Ok(StdioPair {
+                    child: Some(child),
+                    parent: Some(parent.into_file()),
+                })
+            }
+            Self::Null => {
+                // Opened before pivot_root, as the container might not have /dev/null
+                let access = if child_reads {
+                    OFlag::O_RDONLY
+                } else {
+                    OFlag::O_WRONLY
+                };
+                let fd = open("/dev/null", access | OFlag::O_CLOEXEC, Mode::empty())?;
+                Ok(StdioPair {
+                    child: Some(AutoCloseFd { fd }),
+                    parent: None,
+                })
+            }
+            Self::File(path) => {
+                let (flags, mode) = if child_reads {
+                    (OFlag::O_RDONLY, Mode::empty())
+                } else {
+                    (
+                        OFlag::O_WRONLY | OFlag::O_CREAT | OFlag::O_TRUNC,
+                        Mode::from_bits(0o644).unwrap(),
+                    )
+                };
+                let fd = open(path, flags | OFlag::O_CLOEXEC, mode)?;
+                Ok(StdioPair {
+                    child: Some(AutoCloseFd { fd }),
+                    parent: None,
+                })
+            }
+            Self::From(fd) => {
+                let fd = fcntl(*fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+                Ok(StdioPair {
+                    child: Some(AutoCloseFd { fd }),
+                    parent: None,
+                })
+            }
+        }
+    }
+}
+
+/// Writing end of the stdin pipe of a `Process`.
+/// Dropping this closes the pipe, delivering EOF to the process.
+#[derive(Debug)]
+pub struct ChildStdin {
+    pub(crate) inner: File,
+}
+
+impl Write for ChildStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl AsRawFd for ChildStdin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Reading end of the stdout pipe of a `Process`
+#[derive(Debug)]
+pub struct ChildStdout {
+    pub(crate) inner: File,
+}
+
+impl Read for ChildStdout {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl AsRawFd for ChildStdout {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Reading end of the stderr pipe of a `Process`
+#[derive(Debug)]
+pub struct ChildStderr {
+    pub(crate) inner: File,
+}
+
+impl Read for ChildStderr {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl AsRawFd for ChildStderr {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
@@ -0,0 +1,90 @@
+use std::os::unix::io::RawFd;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::unistd::{close, pipe2, read, write};
+
+use crate::AutoCloseFd;
+
+/// Pipe that holds the child after clone until the parent has configured it
+/// with its pid, for instance written the id maps or moved it into a cgroup.
+/// Used instead of `CLONE_VFORK`, which would suspend the parent instead.
+pub(crate) struct SyncPipe {
+    read: AutoCloseFd,
+    write: AutoCloseFd,
+}
+
+impl SyncPipe {
+    pub(crate) fn new() -> nix::Result<Self> {
+        let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+        Ok(Self {
+            read: AutoCloseFd { fd: read },
+            write: AutoCloseFd { fd: write },
+        })
+    }
+
+    /// Reading and writing ends, for `wait_for_parent` in the child
+    pub(crate) fn child_fds(&self) -> (RawFd, RawFd) {
+        (self.read.fd, self.write.fd)
+    }
+
+    /// Lets the child continue
+    pub(crate) fn release(self) -> nix::Result<()> {
+        write(self.write.fd, &[0]).map(drop)
+    }
+
+    /// Closes the pipe without signaling, so the child fails with `ECANCELED`
+    pub(crate) fn abort(self) {
+        drop(self);
+    }
+}
+
+/// Blocks the child until the parent has signaled through the pipe
+pub(crate) fn wait_for_parent(read_fd: RawFd, write_fd: RawFd) -> nix::Result<()> {
+    // The copy of the writing end must be closed, or EOF would never be seen
+    close(write_fd)?;
+    let mut buf = [0; 1];
+    loop {
+        match read(read_fd, &mut buf) {
+            Ok(1) => return Ok(()),
+            // Parent closed the pipe without signaling
+            Ok(_) => return Err(nix::Error::Sys(Errno::ECANCELED)),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::unistd::dup;
+
+    use super::{wait_for_parent, SyncPipe};
+
+    /// Copies of the descriptors, like the child gets from clone
+    fn child_copy(sync: &SyncPipe) -> (i32, i32) {
+        let (read, write) = sync.child_fds();
+        (dup(read).unwrap(), dup(write).unwrap())
+    }
+
+    #[test]
+    fn release() {
+        let sync = SyncPipe::new().unwrap();
+        let (read, write) = child_copy(&sync);
+        let child = std::thread::spawn(move || wait_for_parent(read, write));
+        sync.release().unwrap();
+        assert_eq!(child.join().unwrap(), Ok(()));
+        nix::unistd::close(read).unwrap();
+    }
+
+    #[test]
+    fn abort() {
+        let sync = SyncPipe::new().unwrap();
+        let (read, write) = child_copy(&sync);
+        let child = std::thread::spawn(move || wait_for_parent(read, write));
+        sync.abort();
+        let result = child.join().unwrap();
+        assert_eq!(result, Err(nix::Error::Sys(nix::errno::Errno::ECANCELED)));
+        nix::unistd::close(read).unwrap();
+    }
+}
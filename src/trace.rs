@@ -0,0 +1,60 @@
+//! Instrumentation with the `tracing` crate, enabled by the `tracing` feature.
+//! Without it, spans are empty and events are compiled out.
+//!
+//! Nothing is emitted by the child between clone and exec, as a subscriber
+//! could wait for a lock held by another thread of the parent. Its setup is
+//! reported by the parent once the child has executed the program or failed.
+
+use std::ffi::CStr;
+
+use nix::unistd::Pid;
+
+/// Emits a `tracing` event, taking the level macro name and its arguments
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)+);
+    }};
+}
+pub(crate) use event;
+
+/// Span of a spawned process, carrying its path and, once cloned, its pid
+#[derive(Clone)]
+pub(crate) struct Span {
+    #[cfg(feature = "tracing")]
+    inner: tracing::Span,
+}
+
+#[cfg(feature = "tracing")]
+impl Span {
+    pub(crate) fn process(path: &CStr) -> Self {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = OsStr::from_bytes(path.to_bytes());
+        Self {
+            inner: tracing::info_span!("process", path = ?path, pid = tracing::field::Empty),
+        }
+    }
+
+    pub(crate) fn record_pid(&self, pid: Pid) {
+        self.inner.record("pid", pid.as_raw());
+    }
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.inner.in_scope(f)
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn process(_path: &CStr) -> Self {
+        Self {}
+    }
+
+    pub(crate) fn record_pid(&self, _pid: Pid) {}
+
+    pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        f()
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+
+use nix::errno::Errno;
+use nix::sys::epoll::{
+    epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+};
+
+use crate::{ExitStatus, Process, Result};
+
+/// Future returned by `Process::wait_async`, resolving to the exit status.
+/// Does not depend on any async runtime: the pidfd of the process is watched
+/// by a single thread shared by all futures, which wakes the task once the
+/// process has exited. The process is reaped by the future itself, like
+/// `Process::wait` does.
+///
+/// Kernels older than 5.3 have no pidfd, so there each future starts its own
+/// thread, blocking until the process has exited.
+pub struct WaitFuture<'a> {
+    process: &'a mut Process,
+    wait: Wait,
+}
+
+/// How the future is woken, decided on the first poll
+enum Wait {
+    Idle,
+    /// The pidfd is registered with the shared watcher
+    Watched(RawFd),
+    /// A thread waits for the pid, the waker is updated on every poll
+    Thread(Arc<Mutex<Waker>>),
+}
+
+impl<'a> WaitFuture<'a> {
+    pub(crate) fn new(process: &'a mut Process) -> Self {
+        Self {
+            process,
+            wait: Wait::Idle,
+        }
+    }
+
+    /// Arranges for the task to be woken when the process exits
+    fn register(&mut self, waker: &Waker) -> Result<()> {
+        match &self.wait {
+            Wait::Idle => match self.process.pidfd() {
+                Some(pidfd) => {
+                    watcher()?.watch(pidfd, waker, EpollOp::EpollCtlAdd)?;
+                    self.wait = Wait::Watched(pidfd);
+                }
+                None => {
+                    let shared = Arc::new(Mutex::new(waker.clone()));
+                    self.wait = Wait::Thread(shared.clone());
+                    let pid = self.process.id();
+                    std::thread::spawn(move || {
+                        wait_exited(pid);
+                        shared.lock().unwrap().wake_by_ref();
+                    });
+                }
+            },
+            // Re-armed, as the pidfd is only reported once
+            Wait::Watched(pidfd) => watcher()?.watch(*pidfd, waker, EpollOp::EpollCtlMod)?,
+            Wait::Thread(shared) => {
+                let mut shared = shared.lock().unwrap();
+                if !shared.will_wake(waker) {
+                    *shared = waker.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Future for WaitFuture<'_> {
+    type Output = Result<ExitStatus>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+
+        // Registered before checking, so that an exit in between is not missed
+        if let Err(err) = this.register(cx.waker()) {
+            return Poll::Ready(Err(err));
+        }
+
+        match this.process.try_wait() {
+            Ok(Some(status)) => Poll::Ready(Ok(status)),
+            Ok(None) => Poll::Pending,
+            Err(err) => Poll::Ready(Err(err)),
+        }
+    }
+}
+
+impl Drop for WaitFuture<'_> {
+    fn drop(&mut self) {
+        if let Wait::Watched(pidfd) = self.wait {
+            if let Ok(watcher) = watcher() {
+                watcher.unwatch(pidfd);
+            }
+        }
+    }
+}
+
+/// Epoll instance over the pidfds of the pending futures, waited on by one thread
+struct Watcher {
+    epoll: RawFd,
+    /// Wakers by pidfd, taken by the thread when the pidfd becomes readable
+    wakers: Arc<Mutex<HashMap<RawFd, Waker>>>,
+}
+
+/// Returns the watcher, starting its thread on first use
+fn watcher() -> nix::Result<&'static Watcher> {
+    static WATCHER: OnceLock<nix::Result<Watcher>> = OnceLock::new();
+    WATCHER
+        .get_or_init(Watcher::start)
+        .as_ref()
+        .map_err(|err| *err)
+}
+
+impl Watcher {
+    fn start() -> nix::Result<Self> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let wakers = Arc::new(Mutex::new(HashMap::new()));
+        let shared = Arc::clone(&wakers);
+        std::thread::spawn(move || Self::run(epoll, &shared));
+        Ok(Self { epoll, wakers })
+    }
+
+    /// Wakes the tasks of the exited processes, for the lifetime of the program
+    fn run(epoll: RawFd, wakers: &Mutex<HashMap<RawFd, Waker>>) {
+        let mut events = [EpollEvent::empty(); 16];
+        loop {
+            let count = match epoll_wait(epoll, &mut events, -1) {
+                Ok(count) => count,
+                Err(nix::Error::Sys(Errno::EINTR)) => continue,
+                Err(_) => return,
+            };
+            // Woken outside of the lock, in case the executor polls right away
+            let ready: Vec<Waker> = {
+                let mut wakers = wakers.lock().unwrap();
+                events[..count]
+                    .iter()
+                    .filter_map(|event| wakers.remove(&(event.data() as RawFd)))
+                    .collect()
+            };
+            for waker in ready {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Stores the waker and arms the pidfd for a single readiness event
+    fn watch(&self, pidfd: RawFd, waker: &Waker, op: EpollOp) -> nix::Result<()> {
+        self.wakers.lock().unwrap().insert(pidfd, waker.clone());
+        let flags = EpollFlags::EPOLLIN | EpollFlags::EPOLLONESHOT;
+        let mut event = EpollEvent::new(flags, pidfd as u64);
+        epoll_ctl(self.epoll, op, pidfd, &mut event)
+    }
+
+    /// Removes the pidfd, before it is closed with the process
+    fn unwatch(&self, pidfd: RawFd) {
+        self.wakers.lock().unwrap().remove(&pidfd);
+        let _ = epoll_ctl(self.epoll, EpollOp::EpollCtlDel, pidfd, None);
+    }
+}
+
+/// Blocks until the child process has exited, without reaping it.
+/// Returns early if the process has already been reaped.
+fn wait_exited(pid: u32) {
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        let res = unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) };
+        if res == 0 || Errno::last() != Errno::EINTR {
+            return;
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use isolated::{Capability, Command, Stdio};
+
+/// Runs the command, and returns the effective and bounding capability sets
+fn capabilities(command: Command) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+    let output = command
+        .args(&["Cap", "/proc/self/status"])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+
+    let status = String::from_utf8(output.stdout)?;
+    let field = |name: &str| -> Result<u64, Box<dyn std::error::Error>> {
+        let line = status.lines().find(|line| line.starts_with(name)).unwrap();
+        Ok(u64::from_str_radix(line[name.len()..].trim(), 16)?)
+    };
+    Ok((field("CapEff:")?, field("CapBnd:")?))
+}
+
+#[test]
+fn drop_all_capabilities() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Command::new("rootfs", "/bin/grep").drop_all_capabilities();
+    assert_eq!(capabilities(command)?, (0, 0));
+    Ok(())
+}
+
+#[test]
+fn drop_all_capabilities_non_root() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Command::new("rootfs", "/bin/grep")
+        .uid(1000)
+        .drop_all_capabilities();
+    assert_eq!(capabilities(command)?, (0, 0));
+
+    let command = Command::new("rootfs", "/bin/grep")
+        .uid(1000)
+        .keep_capabilities(&[Capability::Kill]);
+    assert_eq!(capabilities(command)?, (0, 1 << 5));
+    Ok(())
+}
+
+#[test]
+fn keep_capabilities() -> Result<(), Box<dyn std::error::Error>> {
+    let command = Command::new("rootfs", "/bin/grep")
+        .keep_capabilities(&[Capability::NetBindService, Capability::Kill]);
+    let expected = 1 << 10 | 1 << 5;
+    assert_eq!(capabilities(command)?, (expected, expected));
+    Ok(())
+}
+
+#[test]
+fn default_capabilities() -> Result<(), Box<dyn std::error::Error>> {
+    let (effective, _) = capabilities(Command::new("rootfs", "/bin/grep"))?;
+    assert_ne!(effective & 1 << Capability::SysAdmin as u32, 0);
+    Ok(())
+}
@@ -0,0 +1,16 @@
+//! Changes the temporary directory of the whole test process,
+//! so this is kept separate from other tests.
+
+use isolated::Command;
+
+#[test]
+fn drop_running_cleans_up() -> Result<(), Box<dyn std::error::Error>> {
+    let tmp = tempfile::tempdir()?;
+    std::env::set_var("TMPDIR", tmp.path());
+
+    let child = Command::new("rootfs", "/bin/sleep").args(&["30"]).spawn()?;
+    assert_eq!(std::fs::read_dir(tmp.path())?.count(), 1);
+    drop(child);
+    assert_eq!(std::fs::read_dir(tmp.path())?.count(), 0);
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use isolated::{Command, Error, Stdio};
+
+fn shell_output(command: Command) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let output = command.stdout(Stdio::Piped).spawn()?.output()?;
+    Ok(output.stdout)
+}
+
+#[test]
+fn default_env() -> Result<(), Box<dyn std::error::Error>> {
+    std::env::set_var("ISOLATED_HOST_VAR", "leaked");
+    let cmd = Command::new("rootfs", "/bin/sh").args(&["-c", "echo $PATH:$ISOLATED_HOST_VAR"]);
+    assert_eq!(shell_output(cmd)?, b"/usr/bin:/bin:\n");
+    Ok(())
+}
+
+#[test]
+fn set_and_clear_env() -> Result<(), Box<dyn std::error::Error>> {
+    let cmd = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "env | grep -v PWD"])
+        .env_clear()
+        .envs(vec![("FOO", "foo"), ("BAR", "bar")])
+        .env_remove("BAR");
+    assert_eq!(shell_output(cmd)?, b"FOO=foo\n");
+    Ok(())
+}
+
+#[test]
+fn nul_in_env() {
+    let result = Command::new("rootfs", "/bin/true")
+        .env("FOO", "a\0b")
+        .spawn();
+    assert!(matches!(result, Err(Error::InvalidEnv(key)) if key == "FOO"));
+}
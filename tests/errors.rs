@@ -0,0 +1,126 @@
+use std::path::Path;
+
+use isolated::{Command, Error, Resource, SetupStep};
+use nix::errno::Errno;
+
+#[test]
+fn nonexistent_layer() {
+    let result = Command::new("rootfs", "/bin/true")
+        .layer("this-layer-does-not-exist")
+        .spawn();
+    match result {
+        Err(Error::Mount { path, source }) => {
+            assert_eq!(path, Path::new("this-layer-does-not-exist"));
+            assert_eq!(source, Errno::ENOENT);
+        }
+        _ => panic!("Expected a mount error"),
+    }
+}
+
+#[test]
+fn bogus_rootfs() {
+    let result = Command::new("this-rootfs-does-not-exist", "/bin/true").spawn();
+    assert!(matches!(result, Err(Error::Mount { .. })));
+}
+
+#[test]
+fn nul_in_path() -> Result<(), Box<dyn std::error::Error>> {
+    assert!(Command::try_new("rootfs", "/bin/a\0b").is_err());
+    let status = Command::try_new("rootfs", "/bin/true")?.status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn nonexistent_binary() {
+    let result = Command::new("rootfs", "/bin/this-binary-does-not-exist").spawn();
+    match result {
+        Err(Error::Exec { path, source }) => {
+            assert_eq!(path, Path::new("/bin/this-binary-does-not-exist"));
+            assert_eq!(source, Errno::ENOENT);
+        }
+        _ => panic!("Expected an exec error"),
+    }
+}
+
+#[test]
+fn nonexistent_current_dir() {
+    let result = Command::new("rootfs", "/bin/pwd")
+        .current_dir("/this-dir-does-not-exist")
+        .spawn();
+    match result {
+        Err(Error::CurrentDir { path, source }) => {
+            assert_eq!(path, Path::new("/this-dir-does-not-exist"));
+            assert_eq!(source, Errno::ENOENT);
+        }
+        _ => panic!("Expected a current directory error"),
+    }
+}
+
+#[test]
+fn non_utf8_layer() -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // Latin-1 encoded "säie"
+    let dir = tempfile::tempdir()?;
+    let layer = dir.path().join(OsStr::from_bytes(b"s\xe4ie"));
+    std::fs::create_dir(&layer)?;
+
+    let status = Command::new("rootfs", "/bin/true")
+        .layer(&layer)
+        .spawn()?
+        .wait()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn invalid_rlimit() {
+    // Soft limit above the hard limit
+    let result = Command::new("rootfs", "/bin/true")
+        .rlimit(Resource::OpenFiles, 20, 10)
+        .spawn();
+    match result {
+        Err(Error::InvalidRlimit {
+            resource,
+            soft,
+            hard,
+        }) => {
+            assert_eq!(resource, Resource::OpenFiles);
+            assert_eq!((soft, hard), (20, 10));
+        }
+        _ => panic!("Expected an invalid rlimit error"),
+    }
+}
+
+#[test]
+fn failing_setup_step() {
+    // Only root is mapped in the user namespace
+    let result = Command::new("rootfs", "/bin/true")
+        .rootless()
+        .uid(1000)
+        .spawn();
+    assert!(matches!(
+        result,
+        Err(Error::Setup {
+            step: SetupStep::Credentials,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn failing_parent_setup() {
+    // Overlapping entries are rejected when the parent writes the map
+    let result = Command::new("rootfs", "/bin/true")
+        .uid_map(0, 0, 1)
+        .uid_map(0, 1, 1)
+        .gid_map(0, 0, 1)
+        .spawn();
+    assert!(matches!(result, Err(Error::UserNamespace(Errno::EINVAL))));
+
+    // The aborted child has been reaped
+    let children = std::fs::read_to_string("/proc/thread-self/children").unwrap();
+    assert_eq!(children, "");
+}
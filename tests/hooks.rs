@@ -0,0 +1,110 @@
+use isolated::{Command, Error};
+use nix::errno::Errno;
+
+#[test]
+fn hooks_are_called() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let marker = dir.path().join("pre-pivot");
+    let marker_in_hook = marker.clone();
+
+    // Host paths are only visible before pivot_root
+    let status = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "test -f /pre-exec"])
+        .hook_pre_pivot(Box::new(move |()| {
+            std::fs::write(&marker_in_hook, b"").map_err(|_| Errno::EIO)?;
+            Ok(())
+        }))
+        .hook_pre_exec(Box::new(|()| {
+            std::fs::write("/pre-exec", b"").map_err(|_| Errno::EIO)?;
+            Ok(())
+        }))
+        .spawn()?
+        .wait()?;
+
+    assert!(status.success());
+    assert!(marker.exists());
+    Ok(())
+}
+
+#[test]
+fn hooks_run_in_order() -> Result<(), Box<dyn std::error::Error>> {
+    let writedir = tempfile::tempdir()?;
+
+    // Captured state is moved into the hooks
+    let mut command = Command::new("rootfs", "/bin/true").disk_write_to(writedir.path());
+    for name in ["first", "second"] {
+        let marker = format!("/{}", name);
+        command = command.hook_pre_exec(Box::new(move |()| {
+            let count = std::fs::read_dir("/")
+                .map_err(|_| Errno::EIO)?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with("mark-"))
+                .count();
+            std::fs::write(format!("/mark-{}", count), &marker).map_err(|_| Errno::EIO)?;
+            Ok(())
+        }));
+    }
+    let status = command.spawn()?.wait()?;
+
+    assert!(status.success());
+    let path = writedir.path();
+    assert_eq!(std::fs::read_to_string(path.join("mark-0"))?, "/first");
+    assert_eq!(std::fs::read_to_string(path.join("mark-1"))?, "/second");
+    Ok(())
+}
+
+#[test]
+fn failing_hook() {
+    let result = Command::new("rootfs", "/bin/true")
+        .hook_pre_exec(Box::new(|()| Err(Errno::EPERM.into())))
+        .spawn();
+    assert!(matches!(result, Err(Error::Hook(Errno::EPERM))));
+}
+
+#[test]
+fn panicking_hook() {
+    let result = Command::new("rootfs", "/bin/true")
+        .hook_pre_exec(Box::new(|()| panic!("in the child")))
+        .spawn();
+    assert!(matches!(result, Err(Error::ChildPanic)));
+}
+
+/// Uses about `depth` KiB of stack
+fn recurse(depth: usize) -> usize {
+    let buf = std::hint::black_box([depth as u8; 1024]);
+    if depth == 0 {
+        buf[0] as usize
+    } else {
+        recurse(depth - 1) + buf[1023] as usize
+    }
+}
+
+#[test]
+fn hook_stack_usage() -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("rootfs", "/bin/true")
+        .hook_pre_exec(Box::new(|()| {
+            let large = format!("{:?}", vec![0_u8; 64 * 1024]);
+            assert_eq!(large.len(), 3 * 64 * 1024);
+            recurse(256);
+            Ok(())
+        }))
+        .spawn()?
+        .wait()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn hook_stack_overflow() -> Result<(), Box<dyn std::error::Error>> {
+    // The guard page stops the overflow
+    let status = Command::new("rootfs", "/bin/true")
+        .clone_stack_size(64 * 1024)
+        .hook_pre_exec(Box::new(|()| {
+            recurse(1024);
+            Ok(())
+        }))
+        .spawn()?
+        .wait()?;
+    assert_eq!(status.signal(), Some(nix::sys::signal::Signal::SIGSEGV));
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use isolated::{Command, Resource, Stdio, RLIM_INFINITY};
+
+#[test]
+fn rlimit() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "ulimit -n; ulimit -H -n; ulimit -f"])
+        .rlimit(Resource::OpenFiles, 10, 20)
+        .rlimit(Resource::FileSize, RLIM_INFINITY, RLIM_INFINITY)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"10\n20\nunlimited\n");
+    Ok(())
+}
+
+#[test]
+fn rlimit_conveniences() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "ulimit -n; ulimit -c; ulimit -H -c; ulimit -t; ulimit -v",
+        ])
+        .rlimit_nofile(32)
+        .rlimit_core(0)
+        .rlimit_cpu(5)
+        .rlimit_as(256 * 1024 * 1024)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"32\n0\n0\n5\n262144\n");
+    Ok(())
+}
+
+/// Whether cgroup v2 with the given controller is available on the host
+fn has_cgroup_controller(name: &str) -> bool {
+    std::fs::read_to_string("/sys/fs/cgroup/cgroup.controllers")
+        .map(|controllers| controllers.split_whitespace().any(|c| c == name))
+        .unwrap_or(false)
+}
+
+#[test]
+fn memory_limit() -> Result<(), Box<dyn std::error::Error>> {
+    if !has_cgroup_controller("memory") {
+        eprintln!("Skipped, cgroup v2 memory controller is not available");
+        return Ok(());
+    }
+
+    // Files in tmpfs are charged to the cgroup
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "yes | head -c 64M > /dev/shm/fill"])
+        .memory_limit_bytes(16 * 1024 * 1024)
+        .spawn()?;
+    let status = child.wait()?;
+    assert!(!status.success());
+    assert!(child.oom_killed());
+    Ok(())
+}
+
+#[test]
+fn cgroup_not_mounted() {
+    let base = tempfile::tempdir().unwrap();
+    let result = Command::new("rootfs", "/bin/true")
+        .memory_limit_bytes(16 * 1024 * 1024)
+        .cgroup_base(base.path())
+        .spawn();
+    assert!(matches!(
+        result,
+        Err(isolated::Error::CgroupUnavailable(path)) if path == base.path()
+    ));
+}
+
+#[test]
+fn time_limit() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::Instant;
+
+    let start = Instant::now();
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["10"])
+        .time_limit(Duration::from_millis(200))
+        .spawn()?;
+    child.wait()?;
+    assert!(child.timed_out());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let mut child = Command::new("rootfs", "/bin/true")
+        .time_limit(Duration::from_secs(10))
+        .spawn()?;
+    assert!(child.wait()?.success());
+    assert!(!child.timed_out());
+    Ok(())
+}
+
+#[test]
+fn pids_limit() -> Result<(), Box<dyn std::error::Error>> {
+    if !has_cgroup_controller("pids") {
+        eprintln!("Skipped, cgroup v2 pids controller is not available");
+        return Ok(());
+    }
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "for i in 1 2 3 4 5 6 7 8; do sleep 1 & done; wait"])
+        .pids_limit(4)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(!output.stderr.is_empty());
+    Ok(())
+}
+
+#[test]
+fn cpu_limit() -> Result<(), Box<dyn std::error::Error>> {
+    if !has_cgroup_controller("cpu") {
+        eprintln!("Skipped, cgroup v2 cpu controller is not available");
+        return Ok(());
+    }
+
+    let base = std::path::Path::new("/sys/fs/cgroup/isolated-test-cpu");
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["5"])
+        .cpu_limit(50)
+        .cpu_weight(200)
+        .cgroup_base(base)
+        .spawn()?;
+
+    let cgroup = std::fs::read_dir(base)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.is_dir())
+        .expect("cgroup of the process");
+    assert_eq!(
+        std::fs::read_to_string(cgroup.join("cpu.max"))?,
+        "50000 100000\n"
+    );
+    assert_eq!(std::fs::read_to_string(cgroup.join("cpu.weight"))?, "200\n");
+
+    child.kill()?;
+    drop(child);
+    assert!(!cgroup.exists());
+    Ok(())
+}
+
+#[test]
+fn cpu_quota() -> Result<(), Box<dyn std::error::Error>> {
+    if !has_cgroup_controller("cpu") {
+        eprintln!("Skipped, cgroup v2 cpu controller is not available");
+        return Ok(());
+    }
+
+    let base = std::path::Path::new("/sys/fs/cgroup/isolated-test-cpu-quota");
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["5"])
+        .cpu_quota(Duration::from_millis(50), Duration::from_millis(75))
+        .cgroup_base(base)
+        .spawn()?;
+
+    let cgroup = std::fs::read_dir(base)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|path| path.is_dir())
+        .expect("cgroup of the process");
+    assert_eq!(
+        std::fs::read_to_string(cgroup.join("cpu.max"))?,
+        "75000 50000\n"
+    );
+    child.kill()?;
+    Ok(())
+}
+
+#[test]
+#[should_panic]
+fn cpu_quota_invalid_period() {
+    let _ = Command::new("rootfs", "/bin/true")
+        .cpu_quota(Duration::from_secs(2), Duration::from_millis(100));
+}
+
+#[test]
+fn max_pids() -> Result<(), Box<dyn std::error::Error>> {
+    // The rlimit fallback does not apply to root
+    let child = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "for i in 1 2 3 4 5 6 7 8 9 10; do sleep 10 & done; echo done",
+        ])
+        .max_pids(5)
+        .uid(1000)
+        .gid(1000)
+        .stdout(Stdio::Null)
+        .stderr(Stdio::Piped)
+        .spawn()?;
+    assert!(child.pid_limit_method().is_some());
+
+    // The shell gives up when it cannot fork, but is still reaped normally
+    let output = child.output()?;
+    assert!(output.status.code().is_some());
+    assert!(String::from_utf8(output.stderr)?.contains("fork"));
+    Ok(())
+}
+
+#[test]
+fn open_files_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "exec 3</bin/sh; echo opened; exec 4</bin/sh; echo opened",
+        ])
+        .rlimit(Resource::OpenFiles, 4, 4)
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"opened\n");
+    assert!(String::from_utf8(output.stderr)?.contains("Too many open files"));
+    Ok(())
+}
+
+#[test]
+fn file_size_limit() -> Result<(), Box<dyn std::error::Error>> {
+    let write = tempfile::tempdir()?;
+
+    // The writer is killed with SIGXFSZ when it exceeds the limit
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "yes | head -c 2000 > /tmp/file; kill -l $?"])
+        .disk_write_to(write.path())
+        .rlimit(Resource::FileSize, 1000, 1000)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"XFSZ\n");
+    assert_eq!(
+        std::fs::metadata(write.path().join("tmp/file"))?.len(),
+        1000
+    );
+    Ok(())
+}
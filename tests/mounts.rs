@@ -0,0 +1,331 @@
+use isolated::{Command, Stdio};
+
+#[test]
+fn read_only() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/touch")
+        .args(&["/tmp/file"])
+        .read_only()
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)?.contains("Read-only file system"));
+    Ok(())
+}
+
+#[test]
+fn read_only_single_layer() -> Result<(), Box<dyn std::error::Error>> {
+    let run = |force_overlayfs| -> Result<String, Box<dyn std::error::Error>> {
+        let output = Command::new("rootfs", "/bin/cat")
+            .args(&["/proc/mounts"])
+            .read_only()
+            .force_overlayfs(force_overlayfs)
+            .stdout(Stdio::Piped)
+            .spawn()?
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+    let root_fs = |mounts: &str| {
+        let root = mounts
+            .lines()
+            .find(|line| line.split(' ').nth(1) == Some("/"));
+        root.and_then(|line| line.split(' ').nth(2))
+            .map(str::to_owned)
+    };
+
+    assert_ne!(root_fs(&run(false)?).as_deref(), Some("overlay"));
+    assert_eq!(root_fs(&run(true)?).as_deref(), Some("overlay"));
+    Ok(())
+}
+
+#[test]
+fn disk_write_tempdir() -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("rootfs", "/bin/touch")
+        .args(&["/tmp/discarded"])
+        .disk_write_tempdir()
+        .spawn()?
+        .wait()?;
+    assert!(status.success());
+    assert!(!std::path::Path::new("rootfs/tmp/discarded").exists());
+    Ok(())
+}
+
+#[test]
+fn into_writedir() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "echo out > /result"])
+        .disk_write_tempdir()
+        .spawn()?;
+    let status = child.wait()?;
+    assert!(status.success());
+    if let Some(mountpoint) = child.mountpoint() {
+        assert_eq!(std::fs::read_to_string(mountpoint.join("result"))?, "out\n");
+    }
+
+    let writedir = child.into_writedir()?;
+    assert_eq!(std::fs::read_to_string(writedir.join("result"))?, "out\n");
+    assert!(!std::path::Path::new("rootfs/result").exists());
+    std::fs::remove_dir_all(writedir.parent().unwrap())?;
+    Ok(())
+}
+
+#[test]
+fn bind() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("input"), "from host\n")?;
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "cat /data/input && echo from container > /data/output",
+        ])
+        .bind(dir.path(), "/data", false)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"from host\n");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("output"))?,
+        "from container\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn bind_read_only() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+
+    let status = Command::new("rootfs", "/bin/touch")
+        .args(&["/mnt/ro/file"])
+        .bind(dir.path(), "/mnt/ro", true)
+        .stderr(Stdio::Null)
+        .spawn()?
+        .wait()?;
+    assert_eq!(status.code(), Some(1));
+    assert!(!dir.path().join("file").exists());
+    Ok(())
+}
+
+#[test]
+fn bind_nested() -> Result<(), Box<dyn std::error::Error>> {
+    let outer = tempfile::tempdir()?;
+    let inner = tempfile::tempdir()?;
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "touch /data/outer && touch /data/inner/file; ls /data/inner",
+        ])
+        .bind(outer.path(), "/data", false)
+        .bind(inner.path(), "/data/inner", true)
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stderr)?.contains("Read-only file system"));
+    assert!(output.stdout.is_empty());
+    assert!(outer.path().join("outer").exists());
+    assert!(!inner.path().join("file").exists());
+    Ok(())
+}
+
+#[test]
+fn tmpfs() -> Result<(), Box<dyn std::error::Error>> {
+    let write = tempfile::tempdir()?;
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "echo scratch > /scratch/file && cat /scratch/file && yes | head -c 2M > /scratch/big",
+        ])
+        .disk_write_to(write.path())
+        .tmpfs("/scratch", Some(1024 * 1024))
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Null)
+        .spawn()?
+        .output()?;
+    // Writing over the size limit fails
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, b"scratch\n");
+    assert!(!write.path().join("scratch/file").exists());
+    Ok(())
+}
+
+#[test]
+fn mount_order() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(dir.path().join("input"), "from host\n")?;
+
+    // A bind inside an earlier tmpfs is visible
+    let output = Command::new("rootfs", "/bin/cat")
+        .args(&["/data/x/input"])
+        .tmpfs("/data", None)
+        .bind(dir.path(), "/data/x", true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"from host\n");
+
+    // A later tmpfs hides the bind
+    let output = Command::new("rootfs", "/bin/ls")
+        .args(&["/data"])
+        .bind(dir.path(), "/data/x", true)
+        .tmpfs("/data", None)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"");
+    Ok(())
+}
+
+#[test]
+fn dev() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "echo discarded > /dev/null && head -c 3 /dev/zero && ls /dev",
+        ])
+        .ipc_namespace(false)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    let (zeros, listing) = output.stdout.split_at(3);
+    assert_eq!(zeros, b"\0\0\0");
+    let listing = String::from_utf8(listing.to_vec())?;
+    for name in &["fd", "full", "null", "random", "shm", "urandom", "zero"] {
+        assert!(
+            listing.lines().any(|line| line == *name),
+            "{} missing",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn dev_pts() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/ls")
+        .args(&["/dev/pts"])
+        .dev_pts(true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"ptmx\n");
+    Ok(())
+}
+
+#[test]
+fn mask_path() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "cat /etc/secret.toml; ls /sys/firmware /etc/secret; \
+             echo x > /proc/sys/kernel/hostname || echo read-only",
+        ])
+        .mask_path("/etc/secret")
+        .mask_path("/etc/secret.toml")
+        .bind("tests", "/etc/secret", true)
+        .bind("Cargo.toml", "/etc/secret.toml", true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "/etc/secret:\n\n/sys/firmware:\nread-only\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn kernel_interfaces_read_only() -> Result<(), Box<dyn std::error::Error>> {
+    // Root in the container, as by default
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "id -u; \
+             echo x > /proc/sys/kernel/hostname || echo hostname; \
+             echo h > /proc/sysrq-trigger || echo sysrq-trigger; \
+             grep -o ' /sys sysfs r[ow]' /proc/mounts",
+        ])
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Null)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "0\nhostname\nsysrq-trigger\n /sys sysfs ro\n"
+    );
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "grep -o ' /sys sysfs r[ow]' /proc/mounts"])
+        .writable_sys(true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.stdout, b" /sys sysfs rw\n");
+    Ok(())
+}
+
+#[test]
+fn masked_paths() -> Result<(), Box<dyn std::error::Error>> {
+    // Replaces the default list, which masks /proc/timer_list
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "head -n 1 /proc/timer_list; ls /etc/secret"])
+        .masked_paths(&["/etc/secret"])
+        .bind("tests", "/etc/secret", true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout)?,
+        "Timer List Version: v0.10\n"
+    );
+    Ok(())
+}
+
+#[test]
+fn hide_pids() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "grep ' /proc ' /proc/mounts"])
+        .hide_pids(true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    let mounts = String::from_utf8(output.stdout)?;
+    assert!(mounts.contains("hidepid="), "{}", mounts);
+    Ok(())
+}
+
+#[test]
+fn writable_layer() -> Result<(), Box<dyn std::error::Error>> {
+    let custom = tempfile::tempdir()?;
+    let session = tempfile::tempdir()?;
+    std::fs::write(custom.path().join("custom"), "customized\n")?;
+
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "cat /custom && echo session > /session"])
+        .writable_layer(custom.path())
+        .disk_write_to(session.path())
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"customized\n");
+    assert_eq!(
+        std::fs::read_to_string(session.path().join("session"))?,
+        "session\n"
+    );
+    assert!(!custom.path().join("session").exists());
+    Ok(())
+}
@@ -0,0 +1,404 @@
+use std::io::Read;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+
+use isolated::{Command, Error, NetworkPolicy, Stdio};
+use nix::errno::Errno;
+
+#[test]
+fn user_namespace() -> Result<(), Box<dyn std::error::Error>> {
+    let uid = nix::unistd::getuid().as_raw();
+    let gid = nix::unistd::getgid().as_raw();
+
+    let output = Command::new("rootfs", "/bin/cat")
+        .args(&["/proc/self/uid_map"])
+        .user_namespace(&[(0, uid, 1)], &[(0, gid, 1)])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    let map = String::from_utf8(output.stdout)?;
+    let fields: Vec<&str> = map.split_whitespace().collect();
+    assert_eq!(fields, ["0", &uid.to_string(), "1"]);
+    Ok(())
+}
+
+#[test]
+fn id_map_entries() -> Result<(), Box<dyn std::error::Error>> {
+    let uid = nix::unistd::getuid().as_raw();
+    let gid = nix::unistd::getgid().as_raw();
+
+    let output = Command::new("rootfs", "/bin/cat")
+        .args(&["/proc/self/uid_map", "/proc/self/gid_map"])
+        .uid_map(0, uid, 1)
+        .gid_map(0, gid, 1)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    let maps = String::from_utf8(output.stdout)?;
+    let fields: Vec<&str> = maps.split_whitespace().collect();
+    assert_eq!(
+        fields,
+        ["0", &uid.to_string(), "1", "0", &gid.to_string(), "1"]
+    );
+    Ok(())
+}
+
+#[test]
+fn hostname() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/cat")
+        .args(&["/proc/sys/kernel/hostname", "/proc/sys/kernel/domainname"])
+        .hostname("isolated-host")
+        .domainname("isolated-domain")
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"isolated-host\nisolated-domain\n");
+    Ok(())
+}
+
+#[test]
+fn default_hostname() -> Result<(), Box<dyn std::error::Error>> {
+    let host = nix::unistd::gethostname(&mut [0; 65])?.to_str()?.to_owned();
+    let output = Command::new("rootfs", "/bin/hostname")
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"isolated\n");
+    assert_eq!(nix::unistd::gethostname(&mut [0; 65])?.to_str()?, host);
+    Ok(())
+}
+
+#[test]
+fn invalid_uts_name() {
+    let long = "x".repeat(65);
+    let result = Command::new("rootfs", "/bin/true").hostname(&long).spawn();
+    assert!(matches!(result, Err(Error::InvalidUtsName(name)) if name == long));
+
+    let result = Command::new("rootfs", "/bin/true")
+        .domainname("a\0b")
+        .spawn();
+    assert!(matches!(result, Err(Error::InvalidUtsName(name)) if name == "a\0b"));
+}
+
+#[test]
+fn ipc_namespace() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "touch /dev/shm/segment && ls /dev/shm"])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"segment\n");
+    assert!(!std::path::Path::new("/dev/shm/segment").exists());
+    Ok(())
+}
+
+#[test]
+fn sysv_ipc_namespace() -> Result<(), Box<dyn std::error::Error>> {
+    let id = unsafe { libc::shmget(libc::IPC_PRIVATE, 4096, libc::IPC_CREAT | 0o600) };
+    Errno::result(id)?;
+    let run = |isolated| -> Result<String, Box<dyn std::error::Error>> {
+        let output = Command::new("rootfs", "/bin/cat")
+            .args(&["/proc/sysvipc/shm"])
+            .ipc_namespace(isolated)
+            .stdout(Stdio::Piped)
+            .spawn()?
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+    let shmid = |table: &str| {
+        table
+            .lines()
+            .skip(1)
+            .any(|line| line.split_whitespace().nth(1) == Some(&id.to_string()))
+    };
+
+    let isolated = run(true);
+    let shared = run(false);
+    unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+    assert!(!shmid(&isolated?));
+    assert!(shmid(&shared?));
+    Ok(())
+}
+
+#[test]
+fn network_policies() -> Result<(), Box<dyn std::error::Error>> {
+    let run = |policy| -> Result<String, Box<dyn std::error::Error>> {
+        let output = Command::new("rootfs", "/bin/sh")
+            .args(&["-c", "ls /sys/class/net; cat /sys/class/net/lo/flags"])
+            .network(policy)
+            .stdout(Stdio::Piped)
+            .spawn()?
+            .output()?;
+        assert!(output.status.success());
+        Ok(String::from_utf8(output.stdout)?)
+    };
+
+    // IFF_UP is 0x1 and IFF_LOOPBACK is 0x8
+    assert_eq!(run(NetworkPolicy::Isolated)?, "lo\n0x9\n");
+    assert_eq!(run(NetworkPolicy::None)?, "lo\n0x8\n");
+
+    let mut host_interfaces: Vec<_> = std::fs::read_dir("/sys/class/net")?
+        .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+        .collect::<std::io::Result<_>>()?;
+    host_interfaces.sort();
+    let host = run(NetworkPolicy::Host)?;
+    assert_eq!(
+        host.lines().take(host_interfaces.len()).collect::<Vec<_>>(),
+        host_interfaces
+    );
+    Ok(())
+}
+
+#[test]
+fn network_veth() -> Result<(), Box<dyn std::error::Error>> {
+    let host_addr = Ipv4Addr::new(10, 200, 0, 1);
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let port = listener.local_addr()?.port();
+
+    let host_veths = || -> std::io::Result<usize> {
+        let entries = std::fs::read_dir("/sys/class/net")?.collect::<Result<Vec<_>, _>>()?;
+        Ok(entries
+            .iter()
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("isolated"))
+            .count())
+    };
+
+    let mut process = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "ls /sys/class/net; cat /sys/class/net/eth0/operstate /proc/net/route",
+        ])
+        .network_veth(host_addr, Ipv4Addr::new(10, 200, 0, 2), 30)
+        .hook_pre_exec(Box::new(move |()| {
+            TcpStream::connect((host_addr, port))
+                .map(drop)
+                .map_err(|err| Errno::from_i32(err.raw_os_error().unwrap_or(0)).into())
+        }))
+        .stdout(Stdio::Piped)
+        .spawn()?;
+    assert_eq!(host_veths()?, 1);
+    let (_, peer) = listener.accept()?;
+    assert_eq!(peer.ip(), Ipv4Addr::new(10, 200, 0, 2));
+
+    let mut stdout = String::new();
+    process.stdout.take().unwrap().read_to_string(&mut stdout)?;
+    assert!(process.wait()?.success());
+    assert!(stdout.starts_with("eth0\nlo\nup\n"));
+    // The default route goes through the host, in little-endian hex
+    assert!(stdout.contains("eth0\t00000000\t0100C80A\t"));
+    assert_eq!(host_veths()?, 0);
+    Ok(())
+}
+
+#[test]
+fn network_veth_nat() -> Result<(), Box<dyn std::error::Error>> {
+    let rules = || -> std::io::Result<Option<String>> {
+        match std::process::Command::new("iptables")
+            .args(["-t", "nat", "-S", "POSTROUTING"])
+            .output()
+        {
+            Ok(output) => Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    };
+    if rules()?.is_none() {
+        eprintln!("Skipped, iptables is not available");
+        return Ok(());
+    }
+
+    let mut process = Command::new("rootfs", "/bin/true")
+        .network(NetworkPolicy::Veth {
+            host_addr: Ipv4Addr::new(10, 200, 1, 1),
+            container_addr: Ipv4Addr::new(10, 200, 1, 2),
+            prefix_len: 30,
+            enable_nat: true,
+        })
+        .spawn()?;
+    assert!(rules()?.unwrap().contains("-s 10.200.1.0/30"));
+    process.wait()?;
+    assert!(!rules()?.unwrap().contains("-s 10.200.1.0/30"));
+    Ok(())
+}
+
+#[test]
+fn share_host_net() -> Result<(), Box<dyn std::error::Error>> {
+    let host_net = std::fs::read_link("/proc/self/ns/net")?;
+    let host_net = host_net.to_str().unwrap();
+
+    let isolated = Command::new("rootfs", "/bin/ls")
+        .args(&["-l", "/proc/self/ns/net"])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(isolated.status.success());
+    assert!(!String::from_utf8(isolated.stdout)?.contains(host_net));
+
+    let shared = Command::new("rootfs", "/bin/ls")
+        .args(&["-l", "/proc/self/ns/net"])
+        .share_host_net()
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(shared.status.success());
+    assert!(String::from_utf8(shared.stdout)?.contains(host_net));
+    Ok(())
+}
+
+/// Also works without root privileges on the host
+#[test]
+fn rootless() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/id")
+        .args(&["-u"])
+        .rootless()
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"0\n");
+    Ok(())
+}
+
+#[test]
+fn no_new_privs() -> Result<(), Box<dyn std::error::Error>> {
+    let run = |enabled| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let output = Command::new("rootfs", "/bin/grep")
+            .args(&["NoNewPrivs:", "/proc/self/status"])
+            .no_new_privs(enabled)
+            .stdout(Stdio::Piped)
+            .spawn()?
+            .output()?;
+        assert!(output.status.success());
+        Ok(output.stdout)
+    };
+
+    // Setuid binaries are executed without gaining privileges
+    assert_eq!(run(true)?, b"NoNewPrivs:\t1\n");
+    assert_eq!(run(false)?, b"NoNewPrivs:\t0\n");
+    Ok(())
+}
+
+#[test]
+fn nosuid() -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    // A setuid copy of id, owned by the user running the tests
+    let layer = tempfile::tempdir()?;
+    std::fs::create_dir(layer.path().join("bin"))?;
+    let suid_id = layer.path().join("bin/suid-id");
+    std::fs::copy("rootfs/bin/id", &suid_id)?;
+    std::fs::set_permissions(&suid_id, std::fs::Permissions::from_mode(0o4755))?;
+
+    let run = |nosuid, no_new_privs| -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let output = Command::new("rootfs", "/bin/suid-id")
+            .layer(layer.path())
+            .args(&["-u"])
+            .uid(1000)
+            .gid(1000)
+            .nosuid(nosuid)
+            .no_new_privs(no_new_privs)
+            .stdout(Stdio::Piped)
+            .spawn()?
+            .output()?;
+        assert!(output.status.success());
+        Ok(output.stdout)
+    };
+
+    // Either one keeps the effective user from changing
+    let owner = format!("{}\n", nix::unistd::geteuid()).into_bytes();
+    assert_eq!(run(true, true)?, b"1000\n");
+    assert_eq!(run(true, false)?, b"1000\n");
+    assert_eq!(run(false, true)?, b"1000\n");
+    assert_eq!(run(false, false)?, owner);
+    Ok(())
+}
+
+#[test]
+fn use_init() -> Result<(), Box<dyn std::error::Error>> {
+    // The orphaned sleep is reparented to the init process
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&[
+            "-c",
+            "echo $$; sh -c 'sleep 0 &'; sleep 0.3; cat /proc/[0-9]*/stat | grep -c ') Z '; exit 4",
+        ])
+        .use_init(true)
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(output.stdout, b"2\n0\n");
+    Ok(())
+}
+
+#[test]
+fn use_init_forwards_signals() -> Result<(), Box<dyn std::error::Error>> {
+    // Without init, sleep would be PID 1 and ignore SIGTERM
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["100"])
+        .use_init(true)
+        .spawn()?;
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    let status = child.terminate(std::time::Duration::from_secs(5))?;
+    assert_eq!(status.code(), Some(128 + libc::SIGTERM));
+    Ok(())
+}
+
+#[test]
+fn use_init_closes_fds() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::Duration;
+
+    // The init process must not keep the stdin pipe open, or cat never exits
+    let mut child = Command::new("rootfs", "/bin/cat")
+        .use_init(true)
+        .stdin(Stdio::Piped)
+        .spawn()?;
+    drop(child.stdin.take());
+    let status = child.wait_timeout(Duration::from_secs(3))?;
+    assert!(status.is_some_and(|status| status.success()));
+
+    let output = Command::new("rootfs", "/bin/cat")
+        .use_init(true)
+        .stdin(Stdio::Bytes(b"from bytes".to_vec()))
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"from bytes");
+    Ok(())
+}
+
+#[test]
+fn grace_period() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::{Duration, Instant};
+
+    // The signal is forwarded by init, and the shell exits by itself
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "trap 'exit 7' TERM; sleep 100 & wait"])
+        .use_init(true)
+        .grace_period(Duration::from_secs(10))
+        .spawn()?;
+    std::thread::sleep(Duration::from_millis(100));
+    let start = Instant::now();
+    assert_eq!(child.shutdown()?.code(), Some(7));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // Without init, SIGTERM is ignored and the process is killed afterwards
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["100"])
+        .grace_period(Duration::from_millis(100))
+        .spawn()?;
+    let status = child.shutdown()?;
+    assert_eq!(status.signal(), Some(nix::sys::signal::Signal::SIGKILL));
+    Ok(())
+}
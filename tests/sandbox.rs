@@ -0,0 +1,47 @@
+use isolated::{SandboxBuilder, Stdio};
+use nix::unistd::Uid;
+
+#[test]
+fn sandbox_spawn() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sandbox = SandboxBuilder::new("rootfs").pool_size(2).build()?;
+    if Uid::effective().is_root() {
+        assert_eq!(sandbox.available(), 2);
+    }
+
+    // Each process gets a fresh root filesystem from the pool
+    for _ in 0..4 {
+        let command = sandbox
+            .command("/bin/sh")
+            .args(&["-c", "ls /tmp; touch /tmp/marker"])
+            .stdout(Stdio::Piped);
+        let output = sandbox.spawn(command)?.output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"");
+    }
+    Ok(())
+}
+
+#[test]
+fn sandbox_unpooled_command() -> Result<(), Box<dyn std::error::Error>> {
+    let mut sandbox = SandboxBuilder::new("rootfs").pool_size(1).build()?;
+    let command = sandbox
+        .command("/bin/touch")
+        .args(&["/file"])
+        .read_only()
+        .stderr(Stdio::Null);
+    let status = sandbox.spawn(command)?.wait()?;
+    assert_eq!(status.code(), Some(1));
+    // The pooled root was not taken by the read-only command
+    if Uid::effective().is_root() {
+        assert_eq!(sandbox.available(), 1);
+    }
+    Ok(())
+}
+
+#[test]
+fn sandbox_invalid_layer() {
+    if Uid::effective().is_root() {
+        let result = SandboxBuilder::new("this-rootfs-does-not-exist").build();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use isolated::{Command, SeccompAction, SeccompPolicy, Stdio};
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+
+#[test]
+fn denied_syscall() -> Result<(), Box<dyn std::error::Error>> {
+    let policy = SeccompPolicy::new(SeccompAction::Allow)
+        .rule(libc::SYS_mkdir, SeccompAction::Errno(Errno::EACCES))
+        .rule(libc::SYS_mkdirat, SeccompAction::Errno(Errno::EACCES));
+    let output = Command::new("rootfs", "/bin/mkdir")
+        .args(&["/tmp/denied"])
+        .seccomp(policy)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8(output.stderr)?.contains("Permission denied"));
+    Ok(())
+}
+
+#[test]
+fn killed_by_syscall() -> Result<(), Box<dyn std::error::Error>> {
+    let policy = SeccompPolicy::new(SeccompAction::Allow)
+        .rule(libc::SYS_mkdir, SeccompAction::KillProcess)
+        .rule(libc::SYS_mkdirat, SeccompAction::KillProcess);
+    let status = Command::new("rootfs", "/bin/mkdir")
+        .args(&["/tmp/killed"])
+        .seccomp(policy.clone())
+        .status()?;
+    assert_eq!(status.signal(), Some(Signal::SIGSYS));
+
+    // Children of the init process are killed the same way
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "mkdir /tmp/killed; kill -l $?"])
+        .seccomp(policy)
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Null)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"SYS\n");
+    Ok(())
+}
+
+#[test]
+fn deny_dangerous() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/grep")
+        .args(&["Seccomp:", "/proc/self/status"])
+        .seccomp(SeccompPolicy::deny_dangerous())
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    // Mode 2 is SECCOMP_MODE_FILTER
+    assert_eq!(output.stdout, b"Seccomp:\t2\n");
+    Ok(())
+}
@@ -1,8 +1,263 @@
-use isolated::{Command, WaitStatus};
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+use isolated::{Command, Error, Stdio, WaitStatus};
+
+#[test]
+fn smoke_test() -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("rootfs", "/bin/pwd").status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn id() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/true").spawn()?;
+    let id = child.id();
+    assert!(std::path::Path::new(&format!("/proc/{}", id)).exists());
+    match child.wait()?.raw() {
+        WaitStatus::Exited(pid, 0) => assert_eq!(pid.as_raw() as u32, id),
+        status => panic!("Unexpected status {:?}", status),
+    }
+    Ok(())
+}
+
+#[test]
+fn exit_status() -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "exit 3"])
+        .status()?;
+    assert!(!status.success());
+    assert_eq!(status.code(), Some(3));
+    assert_eq!(status.signal(), None);
+    assert!(!status.core_dumped());
+    assert_eq!(status.to_string(), "exit code: 3");
+    assert!(matches!(status.raw(), WaitStatus::Exited(_, 3)));
+    Ok(())
+}
+
+#[test]
+fn process_status_helpers() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "exit 3"])
+        .spawn()?;
+    assert_eq!(child.exit_code(), None);
+    assert!(!child.success());
+    child.wait()?;
+    assert_eq!(child.exit_code(), Some(3));
+    assert!(!child.success());
+    assert_eq!(child.signaled(), None);
+
+    let mut child = Command::new("rootfs", "/bin/sleep").args(&["30"]).spawn()?;
+    child.kill()?;
+    child.wait()?;
+    assert_eq!(child.exit_code(), None);
+    assert_eq!(child.signaled(), Some(nix::sys::signal::Signal::SIGKILL));
+    Ok(())
+}
+
+#[test]
+fn pidfd() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "sleep 30"])
+        .spawn()?;
+    let pidfd = child.pidfd().expect("pidfd requires Linux 5.3");
+    let fdinfo = std::fs::read_to_string(format!("/proc/self/fdinfo/{}", pidfd))?;
+    assert!(fdinfo.contains(&format!("Pid:\t{}\n", child.id())));
+
+    child.signal(nix::sys::signal::Signal::SIGKILL)?;
+    let status = child.wait()?;
+    assert_eq!(status.signal(), Some(nix::sys::signal::Signal::SIGKILL));
+    Ok(())
+}
+
+#[test]
+fn try_wait() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["0.2"])
+        .spawn()?;
+    assert_eq!(child.try_wait()?, None);
+    let status = child.wait()?;
+    assert_eq!(child.try_wait()?, Some(status));
+    Ok(())
+}
+
+#[test]
+fn try_wait_exited() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::Duration;
+
+    let mut child = Command::new("rootfs", "/bin/true").spawn()?;
+    let status = child.wait_timeout(Duration::from_secs(10))?;
+    assert!(status.is_some_and(|status| status.success()));
+    assert_eq!(child.try_wait()?, status);
+    assert_eq!(child.wait_timeout(Duration::from_millis(0))?, status);
+    Ok(())
+}
+
+#[test]
+fn kill() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sleep").args(&["30"]).spawn()?;
+    child.kill()?;
+    assert_eq!(
+        child.wait()?.signal(),
+        Some(nix::sys::signal::Signal::SIGKILL)
+    );
+    Ok(())
+}
+
+#[test]
+fn drop_running() -> Result<(), Box<dyn std::error::Error>> {
+    let child = Command::new("rootfs", "/bin/sleep").args(&["30"]).spawn()?;
+    drop(child);
+    Ok(())
+}
+
+#[test]
+fn arg() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/echo")
+        .args(&["a"])
+        .arg("b")
+        .arg("c d")
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.stdout, b"a b c d\n");
+
+    // args replaces the previous arguments
+    let output = Command::new("rootfs", "/bin/echo")
+        .arg("discarded")
+        .args(&["kept"])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.stdout, b"kept\n");
+    Ok(())
+}
+
+#[test]
+fn arg_os() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/echo")
+        .args_os(&[OsStr::new("a")])
+        .arg_os(OsStr::from_bytes(b"\xff"))
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert_eq!(output.stdout, b"a \xff\n");
+
+    let result = Command::new("rootfs", "/bin/echo")
+        .arg_os(OsStr::from_bytes(b"a\0b"))
+        .spawn();
+    assert!(matches!(result, Err(Error::InvalidArg(arg)) if arg.as_bytes() == b"a\0b"));
+    Ok(())
+}
+
+#[test]
+fn current_dir() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/pwd")
+        .current_dir("/tmp")
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"/tmp\n");
+    Ok(())
+}
+
+#[test]
+fn wait_timeout() -> Result<(), Box<dyn std::error::Error>> {
+    use std::time::Duration;
+
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["0.3"])
+        .spawn()?;
+    assert_eq!(child.wait_timeout(Duration::from_millis(10))?, None);
+    let status = child.wait_timeout(Duration::from_secs(10))?;
+    assert!(status.is_some_and(|status| status.success()));
+    Ok(())
+}
+
+#[test]
+fn uid_and_gid() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "id -u; id -g; id -G"])
+        .uid(1000)
+        .gid(1000)
+        .groups(&[1000, 1001])
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"1000\n1000\n1000 1001\n");
+    Ok(())
+}
+
+#[test]
+fn terminate() -> Result<(), Box<dyn std::error::Error>> {
+    use nix::sys::signal::Signal;
+    use std::time::Duration;
+
+    // PID 1 of a namespace ignores signals it has no handler for,
+    // so sleep is killed after the timeout
+    let mut child = Command::new("rootfs", "/bin/sleep")
+        .args(&["100"])
+        .spawn()?;
+    let status = child.terminate(Duration::from_millis(200))?;
+    assert_eq!(status.signal(), Some(Signal::SIGKILL));
+    assert_eq!(child.wait()?, status);
+    assert!(child.signal(Signal::SIGTERM).is_err());
+    Ok(())
+}
+
+/// Minimal executor, polling the future on the current thread
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+
+    struct ThreadWaker(std::thread::Thread);
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(ThreadWaker(std::thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+#[test]
+fn wait_async() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "sleep 0.2; exit 5"])
+        .spawn()?;
+    let status = block_on(child.wait_async())?;
+    assert_eq!(status.code(), Some(5));
+    assert_eq!(child.try_wait()?, Some(status));
+    Ok(())
+}
 
 #[test]
-fn smoke_test() -> nix::Result<()> {
-    let status = Command::new("rootfs", "/bin/pwd").spawn()?.wait()?;
-    assert!(matches!(status, WaitStatus::Exited(_, 0)));
+fn wait_async_many() -> Result<(), Box<dyn std::error::Error>> {
+    let mut children = (0..3)
+        .map(|code| {
+            Command::new("rootfs", "/bin/sh")
+                .args(&["-c", &format!("sleep 0.{}; exit {}", 3 - code, code)])
+                .spawn()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // The futures share the thread watching the pidfds
+    std::thread::scope(|scope| {
+        for (code, child) in children.iter_mut().enumerate() {
+            scope.spawn(move || {
+                let status = block_on(child.wait_async()).unwrap();
+                assert_eq!(status.code(), Some(code as i32));
+            });
+        }
+    });
     Ok(())
 }
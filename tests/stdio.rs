@@ -0,0 +1,142 @@
+use std::io::{Read, Write};
+
+use isolated::{Command, Stdio};
+
+#[test]
+fn capture_stdout() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/echo")
+        .args(&["hello"])
+        .stdout(Stdio::Piped)
+        .spawn()?;
+
+    let status = child.wait()?;
+    assert!(status.success());
+
+    let mut output = Vec::new();
+    child.stdout.take().unwrap().read_to_end(&mut output)?;
+    assert_eq!(output, b"hello\n");
+    Ok(())
+}
+
+#[test]
+fn capture_stderr() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "echo out; echo err >&2"])
+        .stdout(Stdio::Null)
+        .stderr(Stdio::Piped)
+        .spawn()?;
+
+    let mut output = Vec::new();
+    child.stderr.take().unwrap().read_to_end(&mut output)?;
+    assert_eq!(output, b"err\n");
+
+    let status = child.wait()?;
+    assert!(status.success());
+    Ok(())
+}
+
+#[test]
+fn stdout_to_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("out.txt");
+
+    let status = Command::new("rootfs", "/bin/echo")
+        .args(&["to file"])
+        .stdout(Stdio::File(path.clone()))
+        .spawn()?
+        .wait()?;
+    assert!(status.success());
+
+    assert_eq!(std::fs::read(path)?, b"to file\n");
+    Ok(())
+}
+
+#[test]
+fn process_output() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "echo out; echo err >&2; exit 3"])
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.stderr, b"err\n");
+    Ok(())
+}
+
+#[test]
+fn output_large_stderr() -> Result<(), Box<dyn std::error::Error>> {
+    // More than a pipe buffer worth of stderr before anything on stdout
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "yes | head -c 200000 >&2; echo done"])
+        .stdout(Stdio::Piped)
+        .stderr(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"done\n");
+    assert_eq!(output.stderr.len(), 200000);
+    Ok(())
+}
+
+#[test]
+fn stdin_roundtrip() -> Result<(), Box<dyn std::error::Error>> {
+    let mut child = Command::new("rootfs", "/bin/cat")
+        .stdin(Stdio::Piped)
+        .stdout(Stdio::Piped)
+        .spawn()?;
+
+    // Dropping the handle closes the pipe so that cat terminates
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"round trip")?;
+    drop(stdin);
+
+    let output = child.output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"round trip");
+    Ok(())
+}
+
+#[test]
+fn stdin_from_bytes() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .stdin(Stdio::Bytes(b"echo from script\nexit 5\n".to_vec()))
+        .stdout(Stdio::Piped)
+        .spawn()?
+        .output()?;
+
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, b"from script\n");
+    Ok(())
+}
+
+#[test]
+fn command_output() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "echo out; echo err >&2; exit 3"])
+        .output()?;
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(output.stdout, b"out\n");
+    assert_eq!(output.stderr, b"err\n");
+    Ok(())
+}
+
+#[test]
+fn command_output_null_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    let output = Command::new("rootfs", "/bin/cat").output()?;
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    Ok(())
+}
+
+#[test]
+fn command_status() -> Result<(), Box<dyn std::error::Error>> {
+    let status = Command::new("rootfs", "/bin/sh")
+        .args(&["-c", "exit 7"])
+        .status()?;
+    assert_eq!(status.code(), Some(7));
+    Ok(())
+}